@@ -0,0 +1,322 @@
+//! This module handles static site building.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use actix_service::Service;
+use actix_web::{
+    dev::ServiceResponse,
+    test::{call_service, read_body, TestRequest},
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+
+use crate::{
+    archive_date_elements, count_archive_periods, duplicate_urls, escape_xml, has_taxonomy_list,
+    init_app_service, load_posts, url_for, walk_folder, FeedFormat, Instance, PressError,
+    PressResult, NO_ELEMENTS, PATH_ESCAPES,
+};
+
+/// File put in the output folder, so that a later build knows it can replace the folder.
+const BUILD_MARKER: &str = ".pressure-build";
+
+/// Build Pressure instance into a static site, which can be hosted on any file server.
+/// An existing output folder is replaced only if it's empty or built by Pressure before,
+/// unless `clean` is given.
+pub fn build<T: Into<PathBuf>>(
+    instance: Instance,
+    output_folder: T,
+    clean: bool,
+) -> PressResult<()> {
+    let output_folder = output_folder.into();
+    if output_folder.exists() {
+        if instance
+            .root_folder
+            .canonicalize()?
+            .starts_with(output_folder.canonicalize()?)
+        {
+            return Err(PressError::new(
+                "Output folder must not contain the instance.",
+            ));
+        }
+        let replaceable = output_folder.join(BUILD_MARKER).is_file()
+            || fs::read_dir(&output_folder)?.next().is_none();
+        if !replaceable && !clean {
+            return Err(PressError::new(&format!(
+                "Output folder {} is not built by Pressure, pass --clean to replace it anyway.",
+                output_folder.display()
+            )));
+        }
+        fs::remove_dir_all(&output_folder)?;
+    }
+    fs::create_dir_all(&output_folder)?;
+    fs::write(output_folder.join(BUILD_MARKER), "")?;
+
+    // raw files go first, so that they never shadow rendered pages
+    copy_folder(&instance.raw_folder, &output_folder)?;
+    copy_folder(&instance.static_folder, &output_folder.join("static"))?;
+    copy_folder(
+        &instance.theme_static_folder,
+        &output_folder.join("theme").join("static"),
+    )?;
+
+    actix_web::rt::System::new("build").block_on(async move {
+        let mut app = init_app_service(&instance).await?;
+        // the first request makes the app remember its routes, so that `url_for` works
+        freeze(&mut app, "/", &output_folder).await?;
+        // redirects go before rendered pages, so that on case-insensitive file systems
+        // a page overwrites the redirect to itself, e.g. `/tag/Rust/` -> `/tag/rust/`
+        for url in collect_term_redirects(&instance)? {
+            freeze(&mut app, &url, &output_folder).await?;
+        }
+        for url in collect_urls(&instance)? {
+            freeze(&mut app, &url, &output_folder).await?;
+        }
+        Ok(())
+    })
+}
+
+/// Collect URLs of all rendered resources, except the index.
+fn collect_urls(instance: &Instance) -> PressResult<Vec<String>> {
+    let posts = load_posts(instance, true);
+    let mut urls = vec![];
+
+    for page_num in 2..=instance.config.index_page_count(posts.len()) {
+        urls.push(url_for("index_page", &[page_num.to_string()])?);
+    }
+
+    for post in &posts {
+        urls.push(post.url.clone().unwrap());
+    }
+
+    urls.push(url_for("archive", NO_ELEMENTS)?);
+    for page_num in 2..=instance.config.archive_page_count(posts.len()) {
+        urls.push(url_for("archive_page", &[page_num.to_string()])?);
+    }
+    for ((year, month), post_count) in count_archive_periods(&posts) {
+        let route = if month.is_some() {
+            "archive_month"
+        } else {
+            "archive_year"
+        };
+        let elements = archive_date_elements(year, month);
+        urls.push(url_for(route, &elements)?);
+        for page_num in 2..=instance.config.archive_page_count(post_count) {
+            let mut elements = elements.clone();
+            elements.push(page_num.to_string());
+            urls.push(url_for(&format!("{}_page", route), &elements)?);
+        }
+    }
+    for format in &instance.feed.formats {
+        let name = match format {
+            FeedFormat::Atom => "atom",
+            FeedFormat::Rss => "rss",
+            FeedFormat::Json => "json_feed",
+        };
+        urls.push(url_for(name, NO_ELEMENTS)?);
+    }
+    let atom_feeds = instance.feed.formats.contains(&FeedFormat::Atom);
+    for taxonomy in &instance.taxonomies {
+        if has_taxonomy_list(instance) {
+            urls.push(url_for(&taxonomy.list_route(), NO_ELEMENTS)?);
+        }
+        let route = taxonomy.term_route();
+        for (slug, term) in &taxonomy.count_terms(&posts) {
+            urls.push(url_for(route, &[slug])?);
+            for page_num in 2..=instance.config.archive_page_count(term.count) {
+                urls.push(url_for(
+                    &format!("{}_page", route),
+                    &[slug.clone(), page_num.to_string()],
+                )?);
+            }
+            if atom_feeds && taxonomy.feed {
+                urls.push(url_for(&format!("{}_atom", route), &[slug])?);
+            }
+        }
+    }
+
+    let mut pages = vec![];
+    for rel_path in instance.list_pages()? {
+        let page = instance.load_page(rel_path.with_extension(""))?;
+        if page.is_draft() && !instance.include_drafts {
+            continue;
+        }
+        urls.extend(page.url.clone());
+        pages.push(page);
+    }
+    // an entry would overwrite the file of another one at the same URL
+    if let Some((url, filepaths)) = duplicate_urls(posts.iter().chain(&pages)).pop() {
+        return Err(PressError::new(&format!(
+            "Entries at the same URL {}: {}",
+            url,
+            filepaths.join(", ")
+        )));
+    }
+
+    urls.push(url_for("sitemap_xml", NO_ELEMENTS)?);
+    urls.push(url_for("search_index_json", NO_ELEMENTS)?);
+    urls.push(url_for("robots_txt", NO_ELEMENTS)?);
+    Ok(urls)
+}
+
+/// Collect URLs of terms by their names, which differ from their slugs, e.g. `/tag/C++/`,
+/// so that links made before terms had slugs are redirected to the slugs.
+fn collect_term_redirects(instance: &Instance) -> PressResult<Vec<String>> {
+    let posts = load_posts(instance, true);
+    let mut urls = vec![];
+    for taxonomy in &instance.taxonomies {
+        let names: BTreeSet<&str> = posts
+            .iter()
+            .flat_map(|post| taxonomy.terms(&post.meta))
+            .flat_map(|term| taxonomy.lineage(term))
+            .filter(|term| taxonomy.slug(term) != *term)
+            // such names cannot be file names
+            .filter(|term| {
+                !term
+                    .split('/')
+                    .any(|s| s.is_empty() || s == "." || s == "..")
+            })
+            .collect();
+        for name in names {
+            let name = utf8_percent_encode(name, PATH_ESCAPES).to_string();
+            urls.push(url_for(taxonomy.term_route(), &[name])?);
+        }
+    }
+    Ok(urls)
+}
+
+/// Request the given URL from the app, and write the response body to the output folder,
+/// or a page which redirects to the location if the response is a redirect.
+async fn freeze<S>(app: &mut S, url: &str, output_folder: &Path) -> PressResult<()>
+where
+    S: Service<
+        Request = actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+{
+    let resp = call_service(app, TestRequest::get().uri(url).to_request()).await;
+    let location = resp
+        .headers()
+        .get("Location")
+        .and_then(|location| location.to_str().ok());
+    let body = match location {
+        Some(location) if resp.status().is_redirection() => redirect_page(location).into(),
+        _ if resp.status().is_success() => read_body(resp).await,
+        _ => {
+            return Err(PressError::new(&format!(
+                "Failed to render {}: {}",
+                url,
+                resp.status()
+            )))
+        }
+    };
+    let filepath = output_path(output_folder, url)?;
+    fs::create_dir_all(filepath.parent().unwrap())?;
+    fs::write(filepath, body)?;
+    Ok(())
+}
+
+/// A page which redirects to the given URL, since file servers cannot respond with redirects.
+fn redirect_page(url: &str) -> String {
+    let url = escape_xml(url);
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+        <meta http-equiv=\"refresh\" content=\"0; url={0}\">\
+        <link rel=\"canonical\" href=\"{0}\"></head>\
+        <body><a href=\"{0}\">{0}</a></body></html>\n",
+        url
+    )
+}
+
+/// Map a URL path to the file path which a file server will look up.
+/// e.g. /foo/bar/ -> foo/bar/index.html
+/// It is an error if the file path would be out of the output folder, e.g. /foo/../../bar/.
+fn output_path(output_folder: &Path, url: &str) -> PressResult<PathBuf> {
+    let path = percent_decode_str(url).decode_utf8_lossy();
+    let rel_path = Path::new(path.trim_start_matches('/'));
+    let mut filepath = output_folder.join(rel_path);
+    if !rel_path
+        .components()
+        .all(|comp| matches!(comp, Component::Normal(_)))
+        || !filepath.starts_with(output_folder)
+    {
+        return Err(PressError::new(&format!(
+            "URL {} is out of the output folder",
+            url
+        )));
+    }
+    if path.ends_with('/') {
+        filepath.push("index.html");
+    }
+    Ok(filepath)
+}
+
+fn copy_folder(from: &Path, to: &Path) -> PressResult<()> {
+    for filepath in walk_folder(from)? {
+        let target = to.join(filepath.strip_prefix(from).unwrap());
+        fs::create_dir_all(target.parent().unwrap())?;
+        fs::copy(&filepath, &target)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_path() {
+        let out = Path::new("out");
+        let output_path = |url| output_path(out, url).unwrap();
+        assert_eq!(output_path("/"), out.join("index.html"));
+        assert_eq!(
+            output_path("/post/2020/08/31/test/"),
+            out.join("post/2020/08/31/test/index.html")
+        );
+        assert_eq!(output_path("/foo/bar.html"), out.join("foo/bar.html"));
+        assert_eq!(
+            output_path("/tag/%E5%A5%87%E6%8A%80%E6%B7%AB%E5%B7%A7/"),
+            out.join("tag/奇技淫巧/index.html")
+        );
+        assert!(super::output_path(out, "/post/../../../x/").is_err());
+        assert!(super::output_path(out, "/post/%2E%2E/%2e%2e/x/").is_err());
+        assert!(super::output_path(out, "/./x/").is_err());
+        assert!(super::output_path(out, "//etc/passwd").is_ok());
+    }
+
+    #[test]
+    fn test_build_empty_instance() {
+        let root =
+            std::env::temp_dir().join(format!("pressure-test-empty-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for folder in &["posts", "drafts", "pages"] {
+            fs::create_dir_all(root.join(folder)).unwrap();
+        }
+        fs::copy("tests/test_inst/pressure.toml", root.join("pressure.toml")).unwrap();
+        let templates = root.join("theme").join("templates");
+        fs::create_dir_all(&templates).unwrap();
+        for template in &["index.html", "archive.html"] {
+            fs::write(templates.join(template), "{{ entries | length }} posts").unwrap();
+        }
+        let instance = Instance::new(&root).unwrap();
+        build(instance, root.join("build"), false).unwrap();
+        let index = fs::read_to_string(root.join("build").join("index.html")).unwrap();
+        assert_eq!(index, "0 posts");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_keep_foreign_output_folder() {
+        let out =
+            std::env::temp_dir().join(format!("pressure-test-out-{}", std::process::id()));
+        fs::create_dir_all(&out).unwrap();
+        fs::write(out.join("notes.txt"), "mine").unwrap();
+        let instance = Instance::new("tests/test_inst").unwrap();
+        assert!(build(instance, &out, false).is_err());
+        assert!(out.join("notes.txt").is_file());
+        fs::remove_dir_all(&out).unwrap();
+    }
+}
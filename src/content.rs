@@ -0,0 +1,438 @@
+//! This module handles the in-memory index of entries.
+
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    mem,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, RwLock},
+    thread,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use percent_encoding::percent_decode_str;
+
+use crate::{parse_post_filename, sort_posts, Entry, EntryType, Instance, PressResult};
+
+/// Parsed posts and pages of an instance, kept in memory,
+/// so that requests can be handled without touching the disk.
+#[derive(Debug, Default)]
+pub struct ContentIndex {
+    /// Posts without content, from the newest to the oldest, including unpublished ones,
+    /// so that scheduled posts show up once their time comes.
+    /// They are shared with requests, which are then spared cloning them.
+    posts: Vec<Arc<Entry>>,
+    /// Whether unpublished posts are visible.
+    include_drafts: bool,
+    /// Rendered content of posts, keyed by file name.
+    contents: HashMap<String, String>,
+    /// Pages with content, keyed by path relative to the pages folder.
+    pages: BTreeMap<PathBuf, Arc<Entry>>,
+    /// Posts and then pages at each decoded URL path, of which there should be only one.
+    urls: HashMap<String, Vec<Arc<Entry>>>,
+    /// Incremented whenever the index is changed, so that derived data can be refreshed.
+    version: u64,
+}
+
+/// Content index shared by all workers of the web server.
+pub type SharedIndex = Arc<RwLock<ContentIndex>>;
+
+/// Version of the content index, and the number of creation and expiry times of posts passed.
+pub type PublishedKey = (u64, usize);
+
+impl ContentIndex {
+    /// Build the index by loading all posts and pages of the instance.
+    pub fn new(instance: &Instance) -> PressResult<ContentIndex> {
+        let mut index = ContentIndex::default();
+        index.reload(instance)?;
+        Ok(index)
+    }
+
+    /// Reload all posts and pages from the disk.
+    pub fn reload(&mut self, instance: &Instance) -> PressResult<()> {
+        self.version += 1;
+        self.include_drafts = instance.include_drafts;
+        let mut posts = instance.load_all_posts(false)?;
+        self.contents = posts
+            .iter_mut()
+            .map(|p| (post_key(&p.filepath), mem::take(&mut p.content)))
+            .collect();
+        self.posts = posts.into_iter().map(Arc::new).collect();
+        self.reload_pages(instance)?;
+        self.index_urls();
+        Ok(())
+    }
+
+    fn reload_pages(&mut self, instance: &Instance) -> PressResult<()> {
+        self.pages = instance
+            .list_pages()?
+            .into_iter()
+            .filter_map(|rel_path| {
+                let page = instance.load_page(rel_path.with_extension("")).ok()?;
+                if page.is_draft() && !instance.include_drafts {
+                    return None;
+                }
+                Some((rel_path, Arc::new(page)))
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// Whether a post is visible at the moment.
+    fn is_visible(&self, post: &Entry, now: DateTime<Utc>) -> bool {
+        self.include_drafts || post.is_published(now)
+    }
+
+    /// Posts published at the moment, without content, from the newest to the oldest.
+    pub fn posts(&self) -> Vec<Arc<Entry>> {
+        let now = Utc::now();
+        self.posts
+            .iter()
+            .filter(|p| self.is_visible(p, now))
+            .cloned()
+            .collect()
+    }
+
+    /// Get a published post with its content.
+    pub fn post(&self, year: u16, month: u8, day: u8, name: &str) -> Option<Entry> {
+        let filename = format!("{:04}-{:02}-{:02}-{}.md", year, month, day, name);
+        let post = self
+            .posts()
+            .into_iter()
+            .find(|p| p.filepath.file_name().unwrap_or_default() == filename.as_str())?;
+        Some(self.with_content(&post))
+    }
+
+    /// Fill in the content of a post taken from the index.
+    pub fn load_content(&self, post: &mut Entry) {
+        if let Some(content) = self.contents.get(&post_key(&post.filepath)) {
+            post.content = content.clone();
+        }
+    }
+
+    /// Copy a post taken from the index, with its content.
+    pub fn with_content(&self, post: &Entry) -> Entry {
+        let mut post = post.clone();
+        self.load_content(&mut post);
+        post
+    }
+
+    /// Key of the published posts, which changes whenever the index is changed,
+    /// a scheduled post is published, or a post expires, unlike the number of published posts,
+    /// which stays the same if a post is published while another expires.
+    pub fn published_key(&self) -> PublishedKey {
+        let now = Utc::now();
+        let passed = self
+            .posts
+            .iter()
+            .flat_map(|p| vec![p.created, p.expires])
+            .filter(|dt| dt.map(|dt| dt.with_timezone(&Utc) <= now) == Some(true))
+            .count();
+        (self.version, passed)
+    }
+
+    /// Version of the index, which changes whenever posts or pages are reloaded.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// All pages, keyed by path relative to the pages folder.
+    pub fn pages(&self) -> &BTreeMap<PathBuf, Arc<Entry>> {
+        &self.pages
+    }
+
+    /// Get a published post with its content, or a page, by its decoded URL path, e.g. `/about/`.
+    pub fn entry(&self, url: &str) -> Option<Entry> {
+        let now = Utc::now();
+        let entry = self
+            .urls
+            .get(url)?
+            .iter()
+            .find(|entry| !matches!(entry.etype, EntryType::Post) || self.is_visible(entry, now))?;
+        Some(self.with_content(entry))
+    }
+
+    /// Map URLs to entries again, and warn about entries at the same URL,
+    /// of which only the first can be found.
+    fn index_urls(&mut self) {
+        self.urls.clear();
+        for entry in self.posts.iter().chain(self.pages.values()) {
+            if let Some(url) = &entry.url {
+                let url = percent_decode_str(url).decode_utf8_lossy().to_string();
+                self.urls.entry(url).or_default().push(entry.clone());
+            }
+        }
+        for (url, filepaths) in duplicate_urls(self.posts.iter().chain(self.pages.values())) {
+            log::warn!("Entries at the same URL {}: {}", url, filepaths.join(", "));
+        }
+    }
+
+    /// Update the index after a file in the posts or pages folder is created, modified or removed.
+    pub fn update(&mut self, instance: &Instance, path: &Path) {
+        self.version += 1;
+        if let Some(rel_path) = relative_path(path, &instance.pages_folder) {
+            if rel_path.extension().unwrap_or_default() == "md" {
+                self.update_page(instance, rel_path.to_path_buf());
+            } else if let Err(err) = self.reload_pages(instance) {
+                // maybe a whole folder is changed
+                log::warn!("Failed to reload pages: {}", err);
+            }
+        } else if relative_path(path, &instance.posts_folder).is_some()
+            || relative_path(path, &instance.drafts_folder).is_some()
+        {
+            self.update_post(instance, path);
+        }
+        self.index_urls();
+    }
+
+    fn update_post(&mut self, instance: &Instance, path: &Path) {
+        let key = post_key(path);
+        self.posts.retain(|p| post_key(&p.filepath) != key);
+        self.contents.remove(&key);
+        if !path.is_file() {
+            return;
+        }
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        let (year, month, day, name) = match parse_post_filename(&filename) {
+            Some(parts) => parts,
+            None => return,
+        };
+        // a draft may have the same file name as a post
+        let folder = if relative_path(path, &instance.drafts_folder).is_some() {
+            &instance.drafts_folder
+        } else {
+            &instance.posts_folder
+        };
+        match instance.load_post_from(folder, year, month, day, &name, false) {
+            Ok(mut post) => {
+                self.contents.insert(key, mem::take(&mut post.content));
+                self.posts.push(Arc::new(post));
+                sort_posts(&mut self.posts);
+                log::info!("Reloaded post {}", path.display());
+            }
+            Err(err) => log::warn!("Failed to load post {}: {}", path.display(), err),
+        }
+    }
+
+    fn update_page(&mut self, instance: &Instance, rel_path: PathBuf) {
+        self.pages.remove(&rel_path);
+        if !instance.pages_folder.join(&rel_path).is_file() {
+            return;
+        }
+        match instance.load_page(rel_path.with_extension("")) {
+            Ok(page) if page.is_draft() && !instance.include_drafts => {}
+            Ok(page) => {
+                self.pages.insert(rel_path.clone(), Arc::new(page));
+                log::info!("Reloaded page {}", rel_path.display());
+            }
+            Err(err) => log::warn!("Failed to load page {}: {}", rel_path.display(), err),
+        }
+    }
+}
+
+/// URLs shared by several entries, with file paths of the entries.
+pub(crate) fn duplicate_urls<'a, I, E>(entries: I) -> Vec<(&'a str, Vec<String>)>
+where
+    I: IntoIterator<Item = &'a E>,
+    E: Borrow<Entry> + 'a,
+{
+    let mut filepaths: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for entry in entries {
+        if let Some(url) = &entry.borrow().url {
+            filepaths
+                .entry(url)
+                .or_default()
+                .push(entry.borrow().filepath.display().to_string());
+        }
+    }
+    filepaths
+        .into_iter()
+        .filter(|(_, filepaths)| filepaths.len() > 1)
+        .collect()
+}
+
+/// Posts are identified by their folders and file names, e.g. `drafts/2020-01-01-a.md`,
+/// since a draft may have the same file name as a post.
+fn post_key(filepath: &Path) -> String {
+    let folder = filepath
+        .parent()
+        .and_then(Path::file_name)
+        .unwrap_or_default();
+    Path::new(folder)
+        .join(filepath.file_name().unwrap_or_default())
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Strip the folder from the path, where the path may be either relative to the
+/// folder as is, or to the canonicalized folder.
+pub(crate) fn relative_path<'a>(path: &'a Path, folder: &Path) -> Option<&'a Path> {
+    path.strip_prefix(folder)
+        .ok()
+        .or_else(|| path.strip_prefix(folder.canonicalize().ok()?).ok())
+}
+
+/// Watch the posts and pages folders in a background thread, and keep the index up to date.
+/// `on_change` is called after the index is updated. Watching stops once the returned watcher is dropped.
+pub fn watch_content<F>(
+    instance: &Instance,
+    index: SharedIndex,
+    on_change: F,
+) -> PressResult<RecommendedWatcher>
+where
+    F: Fn() + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    let mut content_watcher = watcher(tx, Duration::from_millis(200))?;
+    for folder in &[
+        &instance.posts_folder,
+        &instance.drafts_folder,
+        &instance.pages_folder,
+    ] {
+        if folder.is_dir() {
+            content_watcher.watch(folder, RecursiveMode::Recursive)?;
+        }
+    }
+    let instance = instance.clone();
+    thread::spawn(move || {
+        // the loop ends when the watcher is dropped
+        for event in rx {
+            let mut index = index.write().unwrap();
+            match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Remove(path) => index.update(&instance, &path),
+                DebouncedEvent::Rename(from, to) => {
+                    index.update(&instance, &from);
+                    index.update(&instance, &to);
+                }
+                DebouncedEvent::Rescan => {
+                    if let Err(err) = index.reload(&instance) {
+                        log::warn!("Failed to reload content: {}", err);
+                    }
+                }
+                _ => continue,
+            }
+            drop(index);
+            on_change();
+        }
+    });
+    Ok(content_watcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_content_index() {
+        let inst = Instance::new("tests/test_inst").unwrap();
+        let index = ContentIndex::new(&inst).unwrap();
+        assert_eq!(index.posts().len(), 2);
+        assert!(index.posts().iter().all(|p| p.content.is_empty()));
+        assert_eq!(index.published_key(), (index.version(), 2));
+        assert_eq!(
+            index.posts()[0].filepath.file_name().unwrap(),
+            "2020-12-27-test-no-content.md"
+        );
+
+        let post = index.post(2020, 8, 31, "test").unwrap();
+        assert!(post.content.contains("<h2>"));
+        assert_eq!(post.toc.len(), 1);
+        assert!(index.post(2020, 8, 31, "not-exists").is_none());
+
+        assert_eq!(
+            index.entry("/post/2020/08/31/test/").unwrap().content,
+            post.content
+        );
+        assert!(index.entry("/test.html").is_some());
+        assert!(index.entry("/post/2020/08/31/not-exists/").is_none());
+
+        assert_eq!(index.pages().len(), 1);
+        assert!(index.entry("/test/").is_none());
+        assert!(index.entry("/not-exists.html").is_none());
+    }
+
+    #[test]
+    fn test_update() {
+        let root =
+            std::env::temp_dir().join(format!("pressure-test-index-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for filepath in crate::walk_folder(Path::new("tests/test_inst")).unwrap() {
+            let target = root.join(filepath.strip_prefix("tests/test_inst").unwrap());
+            fs::create_dir_all(target.parent().unwrap()).unwrap();
+            fs::copy(&filepath, &target).unwrap();
+        }
+        let inst = Instance::new(&root).unwrap();
+        let mut index = ContentIndex::new(&inst).unwrap();
+
+        let new_post = inst.posts_folder.join("2021-01-01-new.md");
+        fs::write(&new_post, "---\ntitle: New\n---\n\nHello").unwrap();
+        let version = index.version();
+        index.update(&inst, &new_post);
+        assert_ne!(index.version(), version);
+        assert_eq!(index.posts().len(), 3);
+        assert_eq!(index.posts()[0].meta["title"].as_str(), Some("New"));
+        assert_eq!(
+            index.post(2021, 1, 1, "new").unwrap().content,
+            "<p>Hello</p>\n"
+        );
+
+        fs::write(&new_post, "---\ntitle: Renamed\n---\n").unwrap();
+        index.update(&inst, &new_post);
+        assert_eq!(index.posts().len(), 3);
+        assert_eq!(index.posts()[0].meta["title"].as_str(), Some("Renamed"));
+
+        fs::remove_file(&new_post).unwrap();
+        index.update(&inst, &new_post);
+        assert_eq!(index.posts().len(), 2);
+
+        let scheduled_post = inst.posts_folder.join("2999-01-01-scheduled.md");
+        fs::write(&scheduled_post, "Soon").unwrap();
+        index.update(&inst, &scheduled_post);
+        let expired_post = inst.posts_folder.join("2020-01-01-expired.md");
+        fs::write(&expired_post, "---\nexpires: 2020-06-01\n---\n").unwrap();
+        index.update(&inst, &expired_post);
+        assert_eq!(index.posts().len(), 2);
+        assert!(index.post(2999, 1, 1, "scheduled").is_none());
+        assert!(index.post(2020, 1, 1, "expired").is_none());
+        // creation and expiry of the expired post have passed, but nothing of the scheduled one
+        assert_eq!(index.published_key().1, 4);
+
+        let new_page = inst.pages_folder.join("foo").join("index.md");
+        fs::create_dir_all(new_page.parent().unwrap()).unwrap();
+        fs::write(&new_page, "Foo").unwrap();
+        index.update(&inst, &new_page);
+        assert!(index.entry("/foo/").is_some());
+        fs::remove_dir_all(new_page.parent().unwrap()).unwrap();
+        index.update(&inst, new_page.parent().unwrap());
+        assert!(index.entry("/foo/").is_none());
+
+        // a draft of the same file name as a post has its own content
+        let mut inst = inst;
+        inst.include_drafts = true;
+        let mut index = ContentIndex::new(&inst).unwrap();
+        let draft = inst.drafts_folder.join("2020-08-31-test.md");
+        fs::write(&draft, "Draft").unwrap();
+        index.update(&inst, &draft);
+        let contents: Vec<String> = index
+            .posts()
+            .into_iter()
+            .filter(|p| p.filepath.file_name().unwrap() == "2020-08-31-test.md")
+            .map(|p| index.with_content(&p).content)
+            .collect();
+        assert_eq!(contents.len(), 2);
+        assert!(contents.contains(&"<p>Draft</p>\n".to_string()));
+        assert!(contents.iter().any(|c| c.contains("<h2>")));
+        let posts = index.posts();
+        let duplicates = duplicate_urls(&posts);
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].0, "/post/2020/08/31/test/");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
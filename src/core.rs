@@ -1,27 +1,79 @@
 //! This module handles entry loading.
 
-use std::{fs, path::PathBuf};
-
-use chrono::{NaiveDate, NaiveDateTime};
-use comrak::{markdown_to_html, ComrakOptions};
+use std::{
+    borrow::Borrow,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use yaml_rust::{yaml, Yaml, YamlLoader};
 
-use crate::{PressError, PressResult};
+use crate::{
+    auto_summary, default_taxonomies, html_to_text, mark_more, merge_taxonomies, normalize_terms,
+    render_markdown, split_more, FeedConfig, MarkdownConfig, PermalinkConfig, PressError,
+    PressResult, ReadingTimeConfig, SlugConfig, SummaryConfig, Taxonomy, TaxonomyConfig, TocItem, WordCount,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Site {
     pub title: String,
+    pub url: Option<String>,
     pub subtitle: Option<String>,
     pub description: Option<String>,
     pub author: Option<String>,
     pub timezone: Option<String>,
 }
 
+impl Site {
+    /// Get the absolute URL of a URL path, based on the site URL.
+    pub fn absolute_url(&self, path: &str) -> String {
+        match &self.url {
+            Some(url) => format!("{}{}", url.trim_end_matches('/'), path),
+            None => path.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub struct Config {
     pub posts_per_index_page: u32,
+    /// Page size of archive, category and tag listings, which are not paginated if not given.
+    #[serde(default)]
+    pub posts_per_archive_page: Option<u32>,
+}
+
+impl Config {
+    /// Check that page sizes are positive.
+    fn validate(&self) -> PressResult<()> {
+        if self.posts_per_index_page == 0 {
+            return Err(PressError::new("`posts_per_index_page` should be positive"));
+        }
+        if self.posts_per_archive_page == Some(0) {
+            return Err(PressError::new("`posts_per_archive_page` should be positive"));
+        }
+        Ok(())
+    }
+
+    /// Number of pages of the index, at least one even if empty.
+    pub fn index_page_count(&self, post_count: usize) -> usize {
+        let posts_per_page = self.posts_per_index_page as usize;
+        ((post_count + posts_per_page - 1) / posts_per_page).max(1)
+    }
+
+    /// Number of pages of an archive, category or tag listing, at least one even if empty.
+    pub fn archive_page_count(&self, post_count: usize) -> usize {
+        match self.posts_per_archive_page {
+            Some(posts_per_page) => {
+                let posts_per_page = posts_per_page as usize;
+                ((post_count + posts_per_page - 1) / posts_per_page).max(1)
+            }
+            None => 1,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -31,10 +83,23 @@ pub struct Instance {
     pub template_folder: PathBuf,
     pub theme_static_folder: PathBuf,
     pub posts_folder: PathBuf,
+    pub drafts_folder: PathBuf,
     pub pages_folder: PathBuf,
     pub raw_folder: PathBuf,
     pub site: Site,
     pub config: Config,
+    pub feed: FeedConfig,
+    pub markdown: MarkdownConfig,
+    pub summary: SummaryConfig,
+    pub reading_time: ReadingTimeConfig,
+    pub slugs: SlugConfig,
+    pub permalinks: PermalinkConfig,
+    /// Built-in taxonomies and the ones given in `pressure.toml`.
+    pub taxonomies: Vec<Taxonomy>,
+    /// Timezone of the site, in which datetimes in front matter are interpreted by default.
+    pub timezone: Tz,
+    /// Whether drafts are included when loading posts.
+    pub include_drafts: bool,
 }
 
 impl Instance {
@@ -44,6 +109,7 @@ impl Instance {
         let template_folder = root_folder.join("theme").join("templates");
         let theme_static_folder = root_folder.join("theme").join("static");
         let posts_folder = root_folder.join("posts");
+        let drafts_folder = root_folder.join("drafts");
         let pages_folder = root_folder.join("pages");
         let raw_folder = root_folder.join("raw");
 
@@ -51,10 +117,44 @@ impl Instance {
         struct Info {
             site: Site,
             config: Config,
+            #[serde(default)]
+            feed: FeedConfig,
+            #[serde(default)]
+            markdown: MarkdownConfig,
+            #[serde(default)]
+            summary: SummaryConfig,
+            #[serde(default)]
+            reading_time: ReadingTimeConfig,
+            #[serde(default)]
+            slugs: SlugConfig,
+            #[serde(default)]
+            permalinks: PermalinkConfig,
+            #[serde(default)]
+            taxonomies: Vec<TaxonomyConfig>,
         }
 
-        let Info { site, config } =
+        let Info {
+            site,
+            config,
+            feed,
+            markdown,
+            summary,
+            reading_time,
+            slugs,
+            permalinks,
+            taxonomies,
+        } =
             toml::from_str(&std::fs::read_to_string(root_folder.join("pressure.toml"))?)?;
+        config.validate()?;
+        let taxonomies = merge_taxonomies(taxonomies, &slugs)?;
+        permalinks.validate(&taxonomies)?;
+        if markdown.highlight.enabled {
+            markdown.highlight.validate()?;
+        }
+        let timezone = match &site.timezone {
+            Some(name) => parse_timezone(name)?,
+            None => Tz::UTC,
+        };
 
         Ok(Instance {
             root_folder,
@@ -62,13 +162,24 @@ impl Instance {
             template_folder,
             theme_static_folder,
             posts_folder,
+            drafts_folder,
             pages_folder,
             raw_folder,
             site,
             config,
+            feed,
+            markdown,
+            summary,
+            reading_time,
+            slugs,
+            permalinks,
+            taxonomies,
+            timezone,
+            include_drafts: false,
         })
     }
 
+    /// Load a post from the posts folder, or from the drafts folder if not found.
     pub fn load_post(
         &self,
         year: u16,
@@ -78,49 +189,77 @@ impl Instance {
         meta_only: bool,
     ) -> PressResult<Entry> {
         let filename = format!("{:04}-{:02}-{:02}-{}.md", year, month, day, name);
-        let mut post = load_entry(EntryType::Post, self.posts_folder.join(filename), meta_only)?;
+        let folder = if self.posts_folder.join(&filename).exists() {
+            &self.posts_folder
+        } else {
+            &self.drafts_folder
+        };
+        self.load_post_from(folder, year, month, day, name, meta_only)
+    }
+
+    pub(crate) fn load_post_from(
+        &self,
+        folder: &Path,
+        year: u16,
+        month: u8,
+        day: u8,
+        name: &str,
+        meta_only: bool,
+    ) -> PressResult<Entry> {
+        let filename = format!("{:04}-{:02}-{:02}-{}.md", year, month, day, name);
+        let mut post = load_entry(
+            EntryType::Post,
+            folder.join(filename),
+            meta_only,
+            &self.markdown,
+            &self.summary,
+            &self.reading_time,
+        )?;
         post.canonicalize_meta(EntryMetaDefaults {
             title: Some(name.split("-").collect::<Vec<&str>>().join(" ")),
             created: Some(
                 NaiveDate::from_ymd(year.into(), month.into(), day.into()).and_hms(0, 0, 0),
             ),
-            ..Default::default()
+            timezone: self.timezone,
+            draft: folder == self.drafts_folder,
+            taxonomies: self.taxonomies.clone(),
         })?;
+        post.url = Some(self.permalinks.post_url(
+            &post,
+            (year, month, day),
+            name,
+            &self.taxonomies,
+        )?);
         Ok(post)
     }
 
+    /// Load all published posts, from the newest to the oldest.
+    /// Drafts, scheduled and expired posts are excluded unless `include_drafts` is set.
     pub fn load_posts(&self, meta_only: bool) -> PressResult<Vec<Entry>> {
-        lazy_static! {
-            static ref POST_FILE_NAME_RE: Regex =
-                Regex::new(r#"^(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})-(?P<name>.+).md$"#)
-                    .unwrap();
+        let now = Utc::now();
+        let mut posts = self.load_all_posts(meta_only)?;
+        posts.retain(|p| self.include_drafts || p.is_published(now));
+        Ok(posts)
+    }
+
+    /// Load all posts, including unpublished ones, from the newest to the oldest.
+    /// The drafts folder is only looked into if `include_drafts` is set.
+    pub(crate) fn load_all_posts(&self, meta_only: bool) -> PressResult<Vec<Entry>> {
+        let mut folders = vec![&self.posts_folder];
+        if self.include_drafts && self.drafts_folder.is_dir() {
+            folders.push(&self.drafts_folder);
         }
-        let mut posts: Vec<Entry> = fs::read_dir(&self.posts_folder)?
-            .filter_map(|dirent| {
+        let mut posts = vec![];
+        for folder in folders {
+            posts.extend(fs::read_dir(folder)?.filter_map(|dirent| {
                 let dirent = dirent.ok()?;
                 let filename = dirent.file_name().to_str()?.to_string();
-                let caps = POST_FILE_NAME_RE.captures(&filename)?;
-                let (year, month, day, name) = (
-                    caps["year"].parse::<u16>().unwrap(),
-                    caps["month"].parse::<u8>().unwrap(),
-                    caps["day"].parse::<u8>().unwrap(),
-                    &caps["name"],
-                );
-                self.load_post(year, month, day, name, meta_only).ok()
-            })
-            .collect();
-        posts.sort_by(|p1, p2| {
-            if p1.created.is_none() && p1.created.is_none() {
-                std::cmp::Ordering::Equal
-            } else if p1.created.is_none() {
-                std::cmp::Ordering::Less
-            } else if p2.created.is_none() {
-                std::cmp::Ordering::Greater
-            } else {
-                // the bigger the datetime, the more front it should be
-                p2.created.unwrap().cmp(&p1.created.unwrap())
-            }
-        });
+                let (year, month, day, name) = parse_post_filename(&filename)?;
+                self.load_post_from(folder, year, month, day, &name, meta_only)
+                    .ok()
+            }));
+        }
+        sort_posts(&mut posts);
         Ok(posts)
     }
 
@@ -143,7 +282,14 @@ impl Instance {
         if filepath.extension().unwrap_or_default() != "md" {
             return Err(PressError::new("Bad page path"));
         }
-        let mut page = load_entry(EntryType::Page, &filepath, false)?;
+        let mut page = load_entry(
+            EntryType::Page,
+            &filepath,
+            false,
+            &self.markdown,
+            &self.summary,
+            &self.reading_time,
+        )?;
         page.canonicalize_meta(EntryMetaDefaults {
             title: Some(
                 filepath
@@ -155,10 +301,214 @@ impl Instance {
                     .collect::<Vec<&str>>()
                     .join(" "),
             ),
+            timezone: self.timezone,
+            taxonomies: self.taxonomies.clone(),
             ..Default::default()
         })?;
+        let rel_path = filepath.strip_prefix(&self.pages_folder).unwrap();
+        page.url = self.permalinks.page_url(&page, rel_path, &self.taxonomies)?;
         Ok(page)
     }
+
+    /// List paths of all page files, relative to the pages folder.
+    pub fn list_pages(&self) -> PressResult<Vec<PathBuf>> {
+        let mut pages: Vec<PathBuf> = walk_folder(&self.pages_folder)?
+            .into_iter()
+            .filter(|filepath| filepath.extension().unwrap_or_default() == "md")
+            .map(|filepath| {
+                filepath
+                    .strip_prefix(&self.pages_folder)
+                    .unwrap()
+                    .to_path_buf()
+            })
+            .collect();
+        pages.sort();
+        Ok(pages)
+    }
+
+    /// Try loading every post and page, and collect the errors.
+    pub fn check_entries(&self) -> PressResult<Vec<(PathBuf, PressError)>> {
+        let mut errors = vec![];
+        for folder in &[&self.posts_folder, &self.drafts_folder] {
+            if !folder.is_dir() {
+                continue;
+            }
+            for dirent in fs::read_dir(folder)? {
+                let filepath = dirent?.path();
+                if filepath.extension().unwrap_or_default() != "md" {
+                    continue;
+                }
+                let filename = filepath.file_name().unwrap().to_str().unwrap_or_default();
+                let res = match parse_post_filename(filename) {
+                    Some((year, month, day, name)) => self
+                        .load_post_from(folder, year, month, day, &name, false)
+                        .map(|_| ()),
+                    None => Err(PressError::new(
+                        "Post file name should be like YYYY-MM-DD-name.md",
+                    )),
+                };
+                if let Err(err) = res {
+                    errors.push((filepath, err));
+                }
+            }
+        }
+        for rel_path in self.list_pages()? {
+            if let Err(err) = self.load_page(rel_path.with_extension("")) {
+                errors.push((self.pages_folder.join(rel_path), err));
+            }
+        }
+        errors.sort_by(|e1, e2| e1.0.cmp(&e2.0));
+        Ok(errors)
+    }
+
+    /// Create a new post file with the given title, in the drafts folder if `draft` is set,
+    /// and return its path.
+    pub fn new_post(&self, title: &str, draft: bool) -> PressResult<PathBuf> {
+        let name = title_to_name(title);
+        if name.is_empty() {
+            return Err(PressError::new("Title should contain letters or digits"));
+        }
+        let now = Utc::now().with_timezone(&self.timezone).naive_local();
+        let filename = format!("{}-{}.md", now.format("%Y-%m-%d"), name);
+        let front_matter = format!(
+            "title: {}\ncreated: {}",
+            yaml_string(title),
+            now.format("%Y-%m-%d %H:%M:%S")
+        );
+        let folder = if draft {
+            &self.drafts_folder
+        } else {
+            &self.posts_folder
+        };
+        create_entry_file(folder.join(filename), &front_matter)
+    }
+
+    /// Create a new page file at the given path relative to the pages folder, and return its path.
+    pub fn new_page<T: Into<PathBuf>>(&self, rel_path: T, title: Option<&str>) -> PressResult<PathBuf> {
+        let mut filepath = self.pages_folder.join(rel_path.into());
+        if !filepath.starts_with(&self.pages_folder)
+            || filepath.components().any(|comp| comp == Component::ParentDir)
+        {
+            return Err(PressError::new("Bad page path"));
+        }
+        filepath.set_extension("md");
+        let title = match title {
+            Some(title) => title.to_string(),
+            None => filepath
+                .file_stem()
+                .unwrap_or_default()
+                .to_str()
+                .unwrap()
+                .split("-")
+                .collect::<Vec<&str>>()
+                .join(" "),
+        };
+        create_entry_file(filepath, &format!("title: {}", yaml_string(&title)))
+    }
+}
+
+pub(crate) fn parse_post_filename(filename: &str) -> Option<(u16, u8, u8, String)> {
+    lazy_static! {
+        static ref POST_FILE_NAME_RE: Regex =
+            Regex::new(r#"^(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})-(?P<name>.+).md$"#)
+                .unwrap();
+    }
+    let caps = POST_FILE_NAME_RE.captures(filename)?;
+    Some((
+        caps["year"].parse::<u16>().unwrap(),
+        caps["month"].parse::<u8>().unwrap(),
+        caps["day"].parse::<u8>().unwrap(),
+        caps["name"].to_string(),
+    ))
+}
+
+/// Sort posts from the newest to the oldest.
+pub(crate) fn sort_posts<E: Borrow<Entry>>(posts: &mut [E]) {
+    posts.sort_by(|p1, p2| {
+        let (p1, p2) = (p1.borrow(), p2.borrow());
+        if p1.created.is_none() && p1.created.is_none() {
+            std::cmp::Ordering::Equal
+        } else if p1.created.is_none() {
+            std::cmp::Ordering::Less
+        } else if p2.created.is_none() {
+            std::cmp::Ordering::Greater
+        } else {
+            // the bigger the datetime, the more front it should be
+            p2.created.unwrap().cmp(&p1.created.unwrap())
+        }
+    });
+}
+
+/// Turn a title into a name usable in file names and URLs.
+/// e.g. "Hello, World!" -> "hello-world"
+fn title_to_name(title: &str) -> String {
+    title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+/// Quote a string as a YAML scalar.
+fn yaml_string(s: &str) -> String {
+    format!("\"{}\"", s.replace("\\", "\\\\").replace("\"", "\\\""))
+}
+
+fn create_entry_file(filepath: PathBuf, front_matter: &str) -> PressResult<PathBuf> {
+    if filepath.exists() {
+        return Err(PressError::new(&format!(
+            "{} already exists",
+            filepath.display()
+        )));
+    }
+    fs::create_dir_all(filepath.parent().unwrap())?;
+    fs::write(&filepath, format!("---\n{}\n---\n\n", front_matter))?;
+    Ok(filepath)
+}
+
+fn parse_timezone(name: &str) -> PressResult<Tz> {
+    name.parse::<Tz>()
+        .or(Err(PressError::new(&format!("Invalid timezone `{}`", name))))
+}
+
+/// Parse a datetime string, either in RFC 3339 format, or in "%Y-%m-%d %H:%M:%S" or "%Y-%m-%d"
+/// format which is interpreted in the given timezone.
+/// The result is always in the given timezone.
+fn parse_datetime(dt_str: &str, timezone: &Tz) -> Option<DateTime<FixedOffset>> {
+    let dt_str = dt_str.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(dt_str) {
+        let dt = dt.with_timezone(timezone);
+        return Some(dt.with_timezone(&dt.offset().fix()));
+    }
+    let naive = NaiveDateTime::parse_from_str(dt_str, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(dt_str, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| NaiveDate::parse_from_str(dt_str, "%Y-%m-%d").map(|d| d.and_hms(0, 0, 0)))
+        .ok()?;
+    localize_datetime(&naive, timezone)
+}
+
+/// Interpret a naive datetime in the given timezone.
+fn localize_datetime(naive: &NaiveDateTime, timezone: &Tz) -> Option<DateTime<FixedOffset>> {
+    let dt = timezone.from_local_datetime(naive).earliest()?;
+    Some(dt.with_timezone(&dt.offset().fix()))
+}
+
+/// List all files in a folder recursively.
+pub(crate) fn walk_folder(folder: &Path) -> PressResult<Vec<PathBuf>> {
+    let mut files = vec![];
+    if !folder.is_dir() {
+        return Ok(files);
+    }
+    for dirent in fs::read_dir(folder)? {
+        let path = dirent?.path();
+        if path.is_dir() {
+            files.append(&mut walk_folder(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -174,9 +524,18 @@ pub struct Entry {
     pub filepath: PathBuf,
     pub url: Option<String>,
     pub meta: Yaml,
-    pub created: Option<NaiveDateTime>,
-    pub updated: Option<NaiveDateTime>,
+    pub created: Option<DateTime<FixedOffset>>,
+    pub updated: Option<DateTime<FixedOffset>>,
+    /// The moment after which the entry is no longer published.
+    pub expires: Option<DateTime<FixedOffset>>,
     pub content: String,
+    /// Table of contents, generated along with the content.
+    pub toc: Vec<TocItem>,
+    /// Rendered summary, generated along with the content.
+    pub summary: String,
+    pub word_count: WordCount,
+    /// Estimated reading time in minutes.
+    pub reading_time: u32,
 }
 
 impl Default for Entry {
@@ -188,7 +547,12 @@ impl Default for Entry {
             meta: Yaml::Hash(yaml::Hash::new()),
             created: None,
             updated: None,
+            expires: None,
             content: "".into(),
+            toc: vec![],
+            summary: "".into(),
+            word_count: WordCount::default(),
+            reading_time: 0,
         }
     }
 }
@@ -196,6 +560,9 @@ impl Default for Entry {
 struct EntryMetaDefaults {
     title: Option<String>,
     created: Option<NaiveDateTime>,
+    timezone: Tz,
+    draft: bool,
+    taxonomies: Vec<Taxonomy>,
 }
 
 impl Default for EntryMetaDefaults {
@@ -203,24 +570,31 @@ impl Default for EntryMetaDefaults {
         Self {
             title: None,
             created: None,
+            timezone: Tz::UTC,
+            draft: false,
+            taxonomies: default_taxonomies(),
         }
     }
 }
 
 impl Entry {
+    /// Whether the entry is a draft, by `draft: true` in front matter or by being in the drafts folder.
+    pub fn is_draft(&self) -> bool {
+        self.meta["draft"] == Yaml::Boolean(true)
+    }
+
+    /// Whether the entry is published at the given moment,
+    /// i.e. it's not a draft, its creation time has come, and it's not expired yet.
+    pub fn is_published(&self, now: DateTime<Utc>) -> bool {
+        let scheduled = self.created.map(|dt| dt.with_timezone(&Utc) > now) == Some(true);
+        let expired = self.expires.map(|dt| dt.with_timezone(&Utc) <= now) == Some(true);
+        !self.is_draft() && !scheduled && !expired
+    }
+
     fn canonicalize_meta(&mut self, defaults: EntryMetaDefaults) -> PressResult<()> {
-        // ensure categories and tags are arrays
-        for key in vec!["categories", "tags"] {
-            if let Some(val) = self.meta.get_mut(key) {
-                if let Yaml::String(_) = val {
-                    *val = Yaml::Array(vec![val.clone()])
-                }
-            } else {
-                self.meta
-                    .as_hash_mut()
-                    .unwrap()
-                    .insert(Yaml::String(key.to_owned()), Yaml::Array(vec![]));
-            }
+        // ensure terms of taxonomies are arrays
+        for taxonomy in &defaults.taxonomies {
+            normalize_terms(&mut self.meta, taxonomy);
         }
 
         // insert default title
@@ -231,58 +605,58 @@ impl Entry {
             );
         }
 
-        // parse created datetime
-        if let Some(Yaml::String(dt_str)) = self.meta.get_mut("created") {
-            if let Ok(dt) = NaiveDateTime::parse_from_str(dt_str, "%Y-%m-%d %H:%M:%S") {
-                self.created = Some(dt);
-            } else if let Ok(d) = NaiveDate::parse_from_str(dt_str, "%Y-%m-%d") {
-                dt_str.push_str(" 00:00:00");
-                self.created = Some(d.and_hms(0, 0, 0));
-            } else {
-                dt_str.clear(); // clear invalid datetime
-            }
-        } else if let Some(created) = defaults.created {
-            self.meta.as_hash_mut().unwrap().insert(
-                Yaml::String("created".to_string()),
-                Yaml::String(format!("{}", created.format("%Y-%m-%d %H:%M:%S"))),
-            );
-            self.created = Some(created);
-        } else {
-            self.meta.as_hash_mut().unwrap().insert(
-                Yaml::String("created".to_string()),
-                Yaml::String("".to_string()),
-            );
-        }
-
-        // parse updated datetime
-        if let Some(Yaml::String(dt_str)) = self.meta.get_mut("updated") {
-            if let Ok(dt) = NaiveDateTime::parse_from_str(dt_str, "%Y-%m-%d %H:%M:%S") {
-                self.updated = Some(dt);
-            } else if let Ok(d) = NaiveDate::parse_from_str(dt_str, "%Y-%m-%d") {
-                dt_str.push_str(" 00:00:00");
-                self.updated = Some(d.and_hms(0, 0, 0));
-            } else {
-                dt_str.clear(); // clear invalid datetime
-            }
-        } else {
-            self.meta.as_hash_mut().unwrap().insert(
-                Yaml::String("created".to_string()),
-                Yaml::String("".to_string()),
-            );
-        }
+        // entries in the drafts folder are always drafts
+        let draft = defaults.draft || self.meta["draft"] == Yaml::Boolean(true);
+        self.meta
+            .as_hash_mut()
+            .unwrap()
+            .insert(Yaml::String("draft".to_string()), Yaml::Boolean(draft));
+
+        // parse datetimes in the timezone of the entry, which defaults to the site's
+        let timezone = match self.meta.get("timezone") {
+            Some(Yaml::String(name)) => parse_timezone(name)?,
+            _ => defaults.timezone,
+        };
+        self.created = self.canonicalize_datetime("created", defaults.created, &timezone);
+        self.updated = self.canonicalize_datetime("updated", None, &timezone);
+        self.expires = self.canonicalize_datetime("expires", None, &timezone);
 
         Ok(())
     }
 
-    pub(crate) fn load_content(&mut self) {
-        let entry = load_entry(EntryType::Post, &self.filepath, false).unwrap();
-        self.content = entry.content;
+    /// Parse a datetime field of front matter, and rewrite it in "%Y-%m-%d %H:%M:%S" format,
+    /// or empty string if it's invalid or missing.
+    fn canonicalize_datetime(
+        &mut self,
+        key: &str,
+        default: Option<NaiveDateTime>,
+        timezone: &Tz,
+    ) -> Option<DateTime<FixedOffset>> {
+        let dt = match self.meta.get(key) {
+            Some(Yaml::String(dt_str)) => parse_datetime(dt_str, timezone),
+            _ => default.and_then(|naive| localize_datetime(&naive, timezone)),
+        };
+        self.meta.as_hash_mut().unwrap().insert(
+            Yaml::String(key.to_string()),
+            Yaml::String(
+                dt.map(|dt| format!("{}", dt.format("%Y-%m-%d %H:%M:%S")))
+                    .unwrap_or_default(),
+            ),
+        );
+        dt
     }
 }
 
 /// Load a Markdown entry, either a post or a page.
 /// If ok, the entry.meta field is guarenteed to be an Yaml::Hash.
-fn load_entry<P>(etype: EntryType, filepath: P, meta_only: bool) -> PressResult<Entry>
+fn load_entry<P>(
+    etype: EntryType,
+    filepath: P,
+    meta_only: bool,
+    markdown: &MarkdownConfig,
+    summary_config: &SummaryConfig,
+    reading_time_config: &ReadingTimeConfig,
+) -> PressResult<Entry>
 where
     P: Into<PathBuf>,
 {
@@ -310,11 +684,21 @@ where
             remained = &tmp_lines[fm_end + 1..];
         }
     }
-    entry.content = if !meta_only {
-        markdown_to_html(remained.join("\n").trim(), &ComrakOptions::default())
-    } else {
-        "".to_string()
-    };
+    if !meta_only {
+        let markdown = markdown.with_overrides(&entry.meta)?;
+        let body = mark_more(remained.join("\n").trim());
+        let (content, toc) = render_markdown(&body, &markdown)?;
+        let (content, more_summary) = split_more(content);
+        // a summary in front matter takes precedence over the `<!-- more -->` marker
+        entry.summary = match entry.meta["summary"].as_str() {
+            Some(summary) => render_markdown(summary, &markdown)?.0,
+            None => more_summary.unwrap_or_else(|| auto_summary(&content, summary_config)),
+        };
+        entry.word_count = WordCount::count(&html_to_text(&content));
+        entry.reading_time = entry.word_count.reading_time(reading_time_config);
+        entry.content = content;
+        entry.toc = toc;
+    }
     Ok(entry)
 }
 
@@ -330,11 +714,39 @@ mod tests {
         assert_eq!(inst.site.title, "My Blog");
         assert_eq!(inst.site.subtitle.unwrap(), "Here is my blog.");
         assert_eq!(inst.config.posts_per_index_page, 5);
+        assert_eq!(inst.config.index_page_count(11), 3);
+        assert_eq!(inst.config.index_page_count(0), 1);
+        assert_eq!(inst.config.archive_page_count(11), 1);
+        let config = Config {
+            posts_per_archive_page: Some(5),
+            ..inst.config
+        };
+        assert_eq!(config.archive_page_count(0), 1);
+        assert_eq!(config.archive_page_count(10), 2);
+        assert!(config.validate().is_ok());
+        let config = Config {
+            posts_per_archive_page: Some(0),
+            ..inst.config
+        };
+        assert!(config.validate().is_err());
+        let config = Config {
+            posts_per_index_page: 0,
+            ..inst.config
+        };
+        assert!(config.validate().is_err());
     }
 
     #[test]
     fn test_load_entry() {
-        let entry = load_entry(EntryType::Page, "tests/test_inst/pages/test.md", false).unwrap();
+        let entry = load_entry(
+            EntryType::Page,
+            "tests/test_inst/pages/test.md",
+            false,
+            &MarkdownConfig::default(),
+            &SummaryConfig::default(),
+            &ReadingTimeConfig::default(),
+        )
+        .unwrap();
         assert!(entry.content.contains("<p>BAZ</p>"));
         assert!(entry.content.contains("<p>FOO BAR!</p>"));
         assert_eq!(entry.meta["title"].as_str().unwrap(), "Foo bar 中文");
@@ -344,7 +756,15 @@ mod tests {
 
     #[test]
     fn test_load_entry_meta_only() {
-        let entry = load_entry(EntryType::Page, "tests/test_inst/pages/test.md", true).unwrap();
+        let entry = load_entry(
+            EntryType::Page,
+            "tests/test_inst/pages/test.md",
+            true,
+            &MarkdownConfig::default(),
+            &SummaryConfig::default(),
+            &ReadingTimeConfig::default(),
+        )
+        .unwrap();
         assert!(entry.content.is_empty());
         assert_eq!(entry.meta["title"].as_str().unwrap(), "Foo bar 中文");
     }
@@ -355,6 +775,9 @@ mod tests {
             EntryType::Page,
             "tests/test_inst/pages/nonexistent.md",
             false,
+            &MarkdownConfig::default(),
+            &SummaryConfig::default(),
+            &ReadingTimeConfig::default(),
         );
         assert!(res.is_err());
     }
@@ -367,9 +790,12 @@ mod tests {
             .unwrap();
         assert_eq!(post.meta["title"].as_str().unwrap(), "test no content");
         assert_eq!(
-            post.created.unwrap(),
+            post.created.unwrap().naive_local(),
             NaiveDate::from_ymd(2020, 12, 27).and_hms(0, 0, 0)
         );
+        assert_eq!(post.created.unwrap().offset().local_minus_utc(), 8 * 3600);
+        assert_eq!(post.meta["created"].as_str().unwrap(), "2020-12-27 00:00:00");
+        assert_eq!(post.meta["updated"].as_str().unwrap(), "");
     }
 
     #[test]
@@ -383,7 +809,11 @@ mod tests {
                 .unwrap(),
             "Dev"
         );
-        assert!(post.content.contains("<h2>喵</h2"));
+        assert!(post.content.contains("></a>喵</h2>"));
+        assert_eq!(post.toc[0].id, "喵");
+        assert!(post.summary.ends_with("<p>这是一个测试文章。</p>"));
+        assert_eq!(post.word_count.total(), 9);
+        assert_eq!(post.reading_time, 1);
     }
 
     #[test]
@@ -408,4 +838,93 @@ mod tests {
         }
         assert_eq!(content_count, 0);
     }
+
+    #[test]
+    fn test_post_filename() {
+        assert_eq!(
+            parse_post_filename("2020-08-31-test.md"),
+            Some((2020, 8, 31, "test".to_string()))
+        );
+        assert_eq!(parse_post_filename("test.md"), None);
+        assert_eq!(title_to_name("Hello, World!"), "hello-world");
+        assert_eq!(title_to_name("C++ 奇技淫巧"), "c-奇技淫巧");
+    }
+
+    #[test]
+    fn test_list_pages() {
+        let inst = Instance::new("tests/test_inst").unwrap();
+        assert_eq!(inst.list_pages().unwrap(), vec![PathBuf::from("test.md")]);
+        assert!(inst.check_entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_datetime() {
+        let tz: Tz = "Asia/Shanghai".parse().unwrap();
+        let dt = parse_datetime("2020-12-26 00:50:00", &tz).unwrap();
+        assert_eq!(dt.to_rfc3339(), "2020-12-26T00:50:00+08:00");
+        assert_eq!(
+            dt.with_timezone(&Utc).naive_utc(),
+            NaiveDate::from_ymd(2020, 12, 25).and_hms(16, 50, 0)
+        );
+        let dt = parse_datetime("2020-12-25", &tz).unwrap();
+        assert_eq!(dt.to_rfc3339(), "2020-12-25T00:00:00+08:00");
+        let dt = parse_datetime("2020-12-25T10:00:00+09:00", &tz).unwrap();
+        assert_eq!(dt.to_rfc3339(), "2020-12-25T09:00:00+08:00");
+        let dt = parse_datetime("2020-07-01 12:00:00", &"America/New_York".parse().unwrap());
+        assert_eq!(dt.unwrap().to_rfc3339(), "2020-07-01T12:00:00-04:00");
+        assert!(parse_datetime("yesterday", &tz).is_none());
+    }
+
+    #[test]
+    fn test_entry_timezone() {
+        let mut entry = Entry {
+            meta: YamlLoader::load_from_str("created: 2020-12-25 10:00:00\ntimezone: Asia/Tokyo")
+                .unwrap()
+                .remove(0),
+            ..Entry::default()
+        };
+        entry.canonicalize_meta(EntryMetaDefaults::default()).unwrap();
+        assert_eq!(
+            entry.created.unwrap().to_rfc3339(),
+            "2020-12-25T10:00:00+09:00"
+        );
+    }
+
+    #[test]
+    fn test_drafts() {
+        let mut inst = Instance::new("tests/test_inst").unwrap();
+        assert!(inst.load_posts(true).unwrap().iter().all(|p| !p.is_draft()));
+        let post = inst.load_post(2021, 1, 1, "unfinished", true).unwrap();
+        assert!(post.is_draft());
+        assert_eq!(post.meta["draft"], Yaml::Boolean(true));
+        let post = inst.load_post(2020, 8, 31, "test", true).unwrap();
+        assert_eq!(post.meta["draft"], Yaml::Boolean(false));
+
+        inst.include_drafts = true;
+        let posts = inst.load_posts(true).unwrap();
+        assert_eq!(posts.len(), 3);
+        assert_eq!(posts[0].meta["title"].as_str(), Some("Unfinished"));
+        assert!(posts[0].is_draft());
+    }
+
+    #[test]
+    fn test_is_published() {
+        let tz = Tz::Asia__Shanghai;
+        let entry = Entry {
+            created: parse_datetime("2021-01-01 08:00:00", &tz),
+            expires: parse_datetime("2021-02-01", &tz),
+            ..Entry::default()
+        };
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        assert!(!entry.is_published(at("2020-12-31T23:59:59Z")));
+        assert!(entry.is_published(at("2021-01-01T00:00:00Z")));
+        assert!(entry.is_published(at("2021-01-31T15:59:59Z")));
+        assert!(!entry.is_published(at("2021-01-31T16:00:00Z")));
+
+        let draft = Entry {
+            meta: YamlLoader::load_from_str("draft: true").unwrap().remove(0),
+            ..Entry::default()
+        };
+        assert!(!draft.is_published(at("2021-01-01T00:00:00Z")));
+    }
 }
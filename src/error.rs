@@ -46,3 +46,19 @@ impl From<yaml_rust::ScanError> for PressError {
         }
     }
 }
+
+impl From<tera::Error> for PressError {
+    fn from(err: tera::Error) -> Self {
+        Self {
+            message: format!("{:?}", err),
+        }
+    }
+}
+
+impl From<notify::Error> for PressError {
+    fn from(err: notify::Error) -> Self {
+        Self {
+            message: format!("{:?}", err),
+        }
+    }
+}
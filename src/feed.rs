@@ -0,0 +1,521 @@
+//! This module handles feed generation.
+
+use std::{fmt::Write, fs, path::PathBuf};
+
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::{Position, Url};
+
+use crate::{html_to_text, Entry, Instance};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    Atom,
+    Rss,
+    Json,
+}
+
+impl FeedFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+            FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+            FeedFormat::Json => "application/feed+json; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FeedConfig {
+    /// Enabled feed formats.
+    pub formats: Vec<FeedFormat>,
+    /// Max number of entries in a feed.
+    pub entries: usize,
+    /// Whether to include full content of entries, or only summaries.
+    pub full_content: bool,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        Self {
+            formats: vec![FeedFormat::Atom],
+            entries: 10,
+            full_content: true,
+        }
+    }
+}
+
+/// A media file attached to an entry, given by the `cover` or `audio` field of front matter.
+#[derive(Debug, Clone, PartialEq)]
+struct Enclosure {
+    url: String,
+    mime_type: String,
+    length: u64,
+}
+
+impl Enclosure {
+    /// The URL in front matter may be absolute, e.g. `https://cdn.example.com/a.jpg`,
+    /// relative to the site, e.g. `/static/a.jpg`, or relative to the entry, e.g. `a.jpg`.
+    fn from_entry(instance: &Instance, entry: &Entry, key: &str) -> Option<Enclosure> {
+        let url = entry.meta[key].as_str()?;
+        if url.is_empty() {
+            return None;
+        }
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Some(Enclosure {
+                url: url.to_string(),
+                mime_type: guess_mime_type(url).to_string(),
+                length: 0,
+            });
+        }
+        // resolve the URL against a dummy origin, for the site URL may not be given
+        let root = Url::parse("http://localhost/").unwrap();
+        let path = root
+            .join(entry.url.as_deref().unwrap_or("/"))
+            .and_then(|base| base.join(url))
+            .ok()?[Position::BeforePath..]
+            .to_string();
+        let length = local_file(instance, &path)
+            .and_then(|filepath| fs::metadata(filepath).ok())
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+        Some(Enclosure {
+            url: instance.site.absolute_url(&path),
+            mime_type: guess_mime_type(url).to_string(),
+            length,
+        })
+    }
+}
+
+/// Find the local file served at the given URL path, if any.
+fn local_file(instance: &Instance, url: &str) -> Option<PathBuf> {
+    if !url.starts_with('/') || url.contains("..") {
+        return None;
+    }
+    let filepath = if let Some(rel_path) = url.strip_prefix("/static/") {
+        instance.static_folder.join(rel_path)
+    } else if let Some(rel_path) = url.strip_prefix("/theme/static/") {
+        instance.theme_static_folder.join(rel_path)
+    } else {
+        instance.raw_folder.join(&url[1..])
+    };
+    if filepath.is_file() {
+        Some(filepath)
+    } else {
+        None
+    }
+}
+
+fn guess_mime_type(url: &str) -> &'static str {
+    let ext = url.rsplit('.').next().unwrap_or_default().to_lowercase();
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "aac" => "audio/aac",
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A feed of entries, whose URLs are already generated.
+#[derive(Debug)]
+pub struct Feed<'a> {
+    pub title: String,
+    /// URL path of the feed itself.
+    pub feed_url: String,
+    /// URL path of the web page corresponding to the feed.
+    pub home_url: String,
+    pub entries: &'a [Entry],
+}
+
+impl<'a> Feed<'a> {
+    /// Render the feed in Atom format.
+    pub fn to_atom(&self, instance: &Instance) -> String {
+        let site = &instance.site;
+        let home_url = site.absolute_url(&self.home_url);
+        let mut xml = String::new();
+        xml.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+        write!(xml, "<title>{}</title>", escape_xml(&self.title)).unwrap();
+        if let Some(subtitle) = &site.subtitle {
+            write!(xml, "<subtitle>{}</subtitle>", escape_xml(subtitle)).unwrap();
+        }
+        write!(
+            xml,
+            r#"<link href="{}" rel="self"/><link href="{}"/><id>{}</id>"#,
+            escape_xml(&site.absolute_url(&self.feed_url)),
+            escape_xml(&home_url),
+            escape_xml(&home_url),
+        )
+        .unwrap();
+        write!(xml, "<updated>{}</updated>", rfc3339(&self.updated())).unwrap();
+        write!(
+            xml,
+            "<author><name>{}</name></author>",
+            escape_xml(site.author.as_ref().unwrap_or(&site.title))
+        )
+        .unwrap();
+        xml.push_str("<generator>Pressure</generator>");
+
+        for entry in self.entries {
+            let url = site.absolute_url(entry.url.as_deref().unwrap_or_default());
+            xml.push_str("<entry>");
+            write!(
+                xml,
+                r#"<title>{}</title><link href="{}"/><id>{}</id>"#,
+                escape_xml(entry.meta["title"].as_str().unwrap_or_default()),
+                escape_xml(&url),
+                escape_xml(&url),
+            )
+            .unwrap();
+            if let Some(created) = &entry.created {
+                write!(xml, "<published>{}</published>", rfc3339(created)).unwrap();
+            }
+            if let Some(updated) = entry.updated.as_ref().or(entry.created.as_ref()) {
+                write!(xml, "<updated>{}</updated>", rfc3339(updated)).unwrap();
+            }
+            for term in entry_terms(instance, entry) {
+                write!(xml, r#"<category term="{}"/>"#, escape_xml(term)).unwrap();
+            }
+            for enclosure in entry_enclosures(instance, entry) {
+                write!(
+                    xml,
+                    r#"<link rel="enclosure" href="{}" type="{}" length="{}"/>"#,
+                    escape_xml(&enclosure.url),
+                    escape_xml(&enclosure.mime_type),
+                    enclosure.length
+                )
+                .unwrap();
+            }
+            let tag = if instance.feed.full_content {
+                "content"
+            } else {
+                "summary"
+            };
+            write!(
+                xml,
+                r#"<{0} type="html">{1}</{0}>"#,
+                tag,
+                escape_xml(self.entry_content(instance, entry))
+            )
+            .unwrap();
+            xml.push_str("</entry>");
+        }
+        xml.push_str("</feed>");
+        xml
+    }
+
+    /// Render the feed in RSS 2.0 format.
+    pub fn to_rss(&self, instance: &Instance) -> String {
+        let site = &instance.site;
+        let mut xml = String::new();
+        xml.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push_str(r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">"#);
+        xml.push_str("<channel>");
+        write!(
+            xml,
+            "<title>{}</title><link>{}</link><description>{}</description>",
+            escape_xml(&self.title),
+            escape_xml(&site.absolute_url(&self.home_url)),
+            escape_xml(self.description(instance)),
+        )
+        .unwrap();
+        write!(
+            xml,
+            r#"<atom:link href="{}" rel="self" type="application/rss+xml"/>"#,
+            escape_xml(&site.absolute_url(&self.feed_url)),
+        )
+        .unwrap();
+        write!(
+            xml,
+            "<lastBuildDate>{}</lastBuildDate>",
+            rfc2822(&self.updated())
+        )
+        .unwrap();
+        xml.push_str("<generator>Pressure</generator>");
+
+        for entry in self.entries {
+            let url = site.absolute_url(entry.url.as_deref().unwrap_or_default());
+            xml.push_str("<item>");
+            write!(
+                xml,
+                r#"<title>{}</title><link>{}</link><guid isPermaLink="true">{}</guid>"#,
+                escape_xml(entry.meta["title"].as_str().unwrap_or_default()),
+                escape_xml(&url),
+                escape_xml(&url),
+            )
+            .unwrap();
+            if let Some(created) = &entry.created {
+                write!(xml, "<pubDate>{}</pubDate>", rfc2822(created)).unwrap();
+            }
+            for term in entry_terms(instance, entry) {
+                write!(xml, "<category>{}</category>", escape_xml(term)).unwrap();
+            }
+            // RSS allows only one enclosure per item, audio is preferred
+            if let Some(enclosure) = entry_enclosures(instance, entry).pop() {
+                write!(
+                    xml,
+                    r#"<enclosure url="{}" length="{}" type="{}"/>"#,
+                    escape_xml(&enclosure.url),
+                    enclosure.length,
+                    escape_xml(&enclosure.mime_type)
+                )
+                .unwrap();
+            }
+            write!(
+                xml,
+                "<description>{}</description>",
+                escape_xml(self.entry_content(instance, entry))
+            )
+            .unwrap();
+            xml.push_str("</item>");
+        }
+        xml.push_str("</channel></rss>");
+        xml
+    }
+
+    /// Render the feed in JSON Feed 1.1 format.
+    pub fn to_json(&self, instance: &Instance) -> String {
+        let site = &instance.site;
+        let items: Vec<serde_json::Value> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let url = site.absolute_url(entry.url.as_deref().unwrap_or_default());
+                let mut item = json!({
+                    "id": url,
+                    "url": url,
+                    "title": entry.meta["title"].as_str().unwrap_or_default(),
+                    "tags": entry_terms(instance, entry),
+                });
+                // `content_html` is required, while `summary` is plain text
+                item["content_html"] = json!(self.entry_content(instance, entry));
+                if !instance.feed.full_content {
+                    item["summary"] = json!(html_to_text(&entry.summary).trim());
+                }
+                if let Some(created) = &entry.created {
+                    item["date_published"] = json!(rfc3339(created));
+                }
+                if let Some(updated) = &entry.updated {
+                    item["date_modified"] = json!(rfc3339(updated));
+                }
+                if let Some(cover) = Enclosure::from_entry(instance, entry, "cover") {
+                    item["image"] = json!(cover.url);
+                }
+                if let Some(audio) = Enclosure::from_entry(instance, entry, "audio") {
+                    item["attachments"] = json!([{
+                        "url": audio.url,
+                        "mime_type": audio.mime_type,
+                        "size_in_bytes": audio.length,
+                    }]);
+                }
+                item
+            })
+            .collect();
+        let mut feed = json!({
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": self.title,
+            "home_page_url": site.absolute_url(&self.home_url),
+            "feed_url": site.absolute_url(&self.feed_url),
+            "description": self.description(instance),
+            "items": items,
+        });
+        if let Some(author) = &site.author {
+            feed["authors"] = json!([{ "name": author }]);
+        }
+        feed.to_string()
+    }
+
+    fn description<'b>(&self, instance: &'b Instance) -> &'b str {
+        let site = &instance.site;
+        site.description
+            .as_ref()
+            .or(site.subtitle.as_ref())
+            .unwrap_or(&site.title)
+    }
+
+    /// Full content or summary of an entry, according to the feed config.
+    fn entry_content<'b>(&self, instance: &Instance, entry: &'b Entry) -> &'b str {
+        if instance.feed.full_content {
+            &entry.content
+        } else {
+            &entry.summary
+        }
+    }
+
+    /// The latest time when any entry in the feed was updated.
+    fn updated(&self) -> DateTime<FixedOffset> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.updated.or(entry.created))
+            .max()
+            .unwrap_or_else(|| Utc::now().with_timezone(&FixedOffset::east(0)))
+    }
+}
+
+/// Terms of an entry in all taxonomies, e.g. categories, tags and custom ones.
+fn entry_terms<'a>(instance: &Instance, entry: &'a Entry) -> Vec<&'a str> {
+    instance
+        .taxonomies
+        .iter()
+        .flat_map(|taxonomy| taxonomy.terms(&entry.meta))
+        .collect()
+}
+
+/// Enclosures of an entry, cover first and audio last.
+fn entry_enclosures(instance: &Instance, entry: &Entry) -> Vec<Enclosure> {
+    vec!["cover", "audio"]
+        .into_iter()
+        .filter_map(|key| Enclosure::from_entry(instance, entry, key))
+        .collect()
+}
+
+fn rfc3339(dt: &DateTime<FixedOffset>) -> String {
+    dt.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn rfc2822(dt: &DateTime<FixedOffset>) -> String {
+    dt.to_rfc2822()
+}
+
+/// Escape special characters in XML text and attribute values.
+pub(crate) fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::{Yaml, YamlLoader};
+
+    use super::*;
+    use crate::{merge_taxonomies, TaxonomyConfig};
+
+    #[test]
+    fn test_atom() {
+        let inst = Instance::new("tests/test_inst").unwrap();
+        let mut posts = inst.load_posts(false).unwrap();
+        posts
+            .iter_mut()
+            .for_each(|p| p.url = Some("/post/foo/".to_string()));
+        let feed = Feed {
+            title: inst.site.title.clone(),
+            feed_url: "/atom.xml".to_string(),
+            home_url: "/".to_string(),
+            entries: &posts,
+        };
+        let xml = feed.to_atom(&inst);
+        assert!(xml.contains("<title>My Blog</title>"));
+        assert!(xml.contains(r#"<link href="https://example.com/atom.xml" rel="self"/>"#));
+        assert!(xml.contains("<title>测试</title>"));
+        assert!(xml.contains("<published>2020-08-31T00:00:00+08:00</published>"));
+        assert!(xml.contains(r#"<category term="C++"/>"#));
+        assert!(xml.contains("&lt;/a&gt;喵&lt;/h2&gt;"));
+        assert_eq!(xml.matches("<entry>").count(), 2);
+    }
+
+    #[test]
+    fn test_rss_and_json() {
+        let mut inst = Instance::new("tests/test_inst").unwrap();
+        let mut posts = inst.load_posts(false).unwrap();
+        posts
+            .iter_mut()
+            .for_each(|p| p.url = Some("/post/foo/".to_string()));
+        // terms of custom taxonomies are included as well
+        let series = TaxonomyConfig {
+            name: "series".to_string(),
+            ..TaxonomyConfig::default()
+        };
+        inst.taxonomies = merge_taxonomies(vec![series], &inst.slugs).unwrap();
+        if let Yaml::Hash(meta) = &mut posts[1].meta {
+            let terms = Yaml::Array(vec![Yaml::String("Rust Web".to_string())]);
+            meta.insert(Yaml::String("series".to_string()), terms);
+        }
+        let feed = Feed {
+            title: inst.site.title.clone(),
+            feed_url: "/rss.xml".to_string(),
+            home_url: "/".to_string(),
+            entries: &posts,
+        };
+        let xml = feed.to_rss(&inst);
+        assert!(xml.contains("<link>https://example.com/</link>"));
+        assert!(xml.contains("<pubDate>Mon, 31 Aug 2020 00:00:00 +0800</pubDate>"));
+        assert!(xml.contains("<category>奇技淫巧</category>"));
+        assert!(xml.contains("<category>Rust Web</category>"));
+        assert_eq!(xml.matches("<item>").count(), 2);
+
+        let json: serde_json::Value = serde_json::from_str(&feed.to_json(&inst)).unwrap();
+        assert_eq!(json["version"], "https://jsonfeed.org/version/1.1");
+        assert_eq!(json["authors"][0]["name"], "My Name");
+        assert_eq!(json["items"].as_array().unwrap().len(), 2);
+        assert_eq!(json["items"][1]["title"], "测试");
+        assert_eq!(
+            json["items"][1]["tags"],
+            json!(["Dev", "C++", "奇技淫巧", "Rust Web"])
+        );
+        assert_eq!(
+            json["items"][1]["date_published"],
+            "2020-08-31T00:00:00+08:00"
+        );
+        assert!(json["items"][1]["content_html"].is_string());
+        assert!(json["items"][1]["summary"].is_null());
+
+        inst.feed.full_content = false;
+        let json: serde_json::Value = serde_json::from_str(&feed.to_json(&inst)).unwrap();
+        let summary = json["items"][1]["summary"].as_str().unwrap();
+        assert!(!summary.contains('<'));
+        assert_eq!(json["items"][1]["content_html"], posts[1].summary);
+    }
+
+    #[test]
+    fn test_enclosure() {
+        let inst = Instance::new("tests/test_inst").unwrap();
+        let entry = Entry {
+            meta: YamlLoader::load_from_str("cover: /static/images/1.png\naudio: /foo.mp3")
+                .unwrap()
+                .remove(0),
+            ..Entry::default()
+        };
+        let enclosures = entry_enclosures(&inst, &entry);
+        assert_eq!(enclosures.len(), 2);
+        assert_eq!(enclosures[0].url, "https://example.com/static/images/1.png");
+        assert_eq!(enclosures[0].mime_type, "image/png");
+        assert_eq!(enclosures[1].mime_type, "audio/mpeg");
+        assert_eq!(enclosures[1].length, 0);
+
+        let entry = Entry {
+            meta: YamlLoader::load_from_str("cover: https://cdn.example.com/a.jpg\naudio: a.mp3")
+                .unwrap()
+                .remove(0),
+            url: Some("/post/2020/08/31/test/".to_string()),
+            ..Entry::default()
+        };
+        let enclosures = entry_enclosures(&inst, &entry);
+        assert_eq!(enclosures[0].url, "https://cdn.example.com/a.jpg");
+        assert_eq!(
+            enclosures[1].url,
+            "https://example.com/post/2020/08/31/test/a.mp3"
+        );
+    }
+}
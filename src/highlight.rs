@@ -0,0 +1,331 @@
+//! This module handles syntax highlighting of code blocks.
+
+use std::{fmt::Write, ops::RangeInclusive};
+
+use serde::{Deserialize, Serialize};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Color, Theme, ThemeSet},
+    html::{
+        css_for_theme_with_class_style, styled_line_to_highlighted_html, ClassStyle,
+        ClassedHTMLGenerator, IncludeBackground,
+    },
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+use crate::{escape_xml, PressError, PressResult};
+
+lazy_static! {
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
+}
+
+/// Classes of highlighted tokens are prefixed, so that they don't conflict with the theme's.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+const LINE_NUMBER_STYLE: &str = "margin-right:1em;opacity:0.5;user-select:none";
+
+/// Options of syntax highlighting, given in the `[markdown.highlight]` section of `pressure.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HighlightConfig {
+    pub enabled: bool,
+    /// Name of a built-in theme, used for inline styles, or for the exported stylesheet.
+    pub theme: String,
+    /// Output CSS classes instead of inline styles, to be styled by the exported stylesheet.
+    pub css_classes: bool,
+    pub line_numbers: bool,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            theme: "InspiredGitHub".to_string(),
+            css_classes: false,
+            line_numbers: false,
+        }
+    }
+}
+
+impl HighlightConfig {
+    /// Check if the theme exists.
+    pub fn validate(&self) -> PressResult<()> {
+        find_theme(&self.theme).map(|_| ())
+    }
+}
+
+fn find_theme(name: &str) -> PressResult<&'static Theme> {
+    THEME_SET.themes.get(name).ok_or_else(|| {
+        let names: Vec<&str> = THEME_SET.themes.keys().map(|name| name.as_str()).collect();
+        PressError::new(&format!(
+            "Unknown highlight theme \"{}\", available themes: {}",
+            name,
+            names.join(", ")
+        ))
+    })
+}
+
+fn css_color(color: Color) -> String {
+    if color.a == 0xff {
+        format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+    } else {
+        format!(
+            "rgba({},{},{},{:.2})",
+            color.r,
+            color.g,
+            color.b,
+            color.a as f32 / 255.0
+        )
+    }
+}
+
+/// Style of highlighted lines, which falls back to translucent yellow
+/// if the theme doesn't specify one.
+fn highlighted_line_style(theme: &Theme) -> String {
+    let color = theme.settings.line_highlight.unwrap_or(Color {
+        r: 0xff,
+        g: 0xff,
+        b: 0x00,
+        a: 0x33,
+    });
+    format!("display:block;background-color:{}", css_color(color))
+}
+
+/// Generate the stylesheet of a theme, for code blocks highlighted with CSS classes.
+pub fn highlight_css(theme_name: &str) -> PressResult<String> {
+    let theme = find_theme(theme_name)?;
+    let mut css = css_for_theme_with_class_style(theme, CLASS_STYLE)
+        .map_err(|err| PressError::new(&err.to_string()))?;
+    writeln!(css, ".highlight .line-number {{ {} }}", LINE_NUMBER_STYLE).unwrap();
+    writeln!(
+        css,
+        ".highlight .highlighted {{ {} }}",
+        highlighted_line_style(theme)
+    )
+    .unwrap();
+    Ok(css)
+}
+
+/// Highlight a code block, whose info string is like `rust {3,5-7}`,
+/// where the line numbers in braces are the lines to be emphasized.
+pub(crate) fn highlight_code_block(
+    info: &str,
+    code: &str,
+    config: &HighlightConfig,
+) -> PressResult<String> {
+    let (lang, emphasized) = parse_info(info);
+    let syntax = SYNTAX_SET
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
+    let theme = find_theme(&config.theme)?;
+    let to_press_error = |err: syntect::Error| PressError::new(&err.to_string());
+
+    let html = if config.css_classes {
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAX_SET, CLASS_STYLE);
+        for line in LinesWithEndings::from(code) {
+            generator
+                .parse_html_for_line_which_includes_newline(line)
+                .map_err(to_press_error)?;
+        }
+        generator.finalize()
+    } else {
+        let mut highlighter = HighlightLines::new(syntax, theme);
+        let mut html = String::new();
+        for line in LinesWithEndings::from(code) {
+            let regions = highlighter
+                .highlight_line(line, &SYNTAX_SET)
+                .map_err(to_press_error)?;
+            html.push_str(
+                &styled_line_to_highlighted_html(&regions, IncludeBackground::No)
+                    .map_err(to_press_error)?,
+            );
+        }
+        html
+    };
+
+    let mut out = String::new();
+    if config.css_classes {
+        out.push_str(r#"<pre class="highlight hl-code">"#);
+    } else {
+        let mut style = String::new();
+        if let Some(color) = theme.settings.background {
+            write!(style, "background-color:{};", css_color(color)).unwrap();
+        }
+        if let Some(color) = theme.settings.foreground {
+            write!(style, "color:{};", css_color(color)).unwrap();
+        }
+        write!(out, r#"<pre class="highlight" style="{}">"#, style).unwrap();
+    }
+    if lang.is_empty() {
+        out.push_str("<code>");
+    } else {
+        write!(out, r#"<code class="language-{}">"#, escape_xml(lang)).unwrap();
+    }
+    let lines = split_lines(&html);
+    let width = lines.len().to_string().len();
+    for (i, line) in lines.iter().enumerate() {
+        let line_num = i + 1;
+        if !emphasized.iter().any(|range| range.contains(&line_num)) {
+            out.push_str(r#"<span class="line">"#);
+        } else if config.css_classes {
+            out.push_str(r#"<span class="line highlighted">"#);
+        } else {
+            write!(
+                out,
+                r#"<span class="line highlighted" style="{}">"#,
+                highlighted_line_style(theme)
+            )
+            .unwrap();
+        }
+        if config.line_numbers {
+            let style = if config.css_classes {
+                String::new()
+            } else {
+                format!(r#" style="{}""#, LINE_NUMBER_STYLE)
+            };
+            write!(
+                out,
+                r#"<span class="line-number"{}>{:>width$}</span>"#,
+                style,
+                line_num,
+                width = width
+            )
+            .unwrap();
+        }
+        out.push_str(line);
+        out.push_str("</span>");
+    }
+    out.push_str("</code></pre>\n");
+    Ok(out)
+}
+
+/// Split the info string of a code block into the language and ranges of the lines to be
+/// emphasized, which are kept as ranges, however large. e.g. `rust {3,7-5}` -> ("rust", [3, 5-7])
+fn parse_info(info: &str) -> (&str, Vec<RangeInclusive<usize>>) {
+    let info = info.trim();
+    let (lang, attrs) = match info.find(|c: char| c.is_whitespace() || c == '{') {
+        Some(pos) => (&info[..pos], info[pos..].trim()),
+        None => (info, ""),
+    };
+    let mut lines = vec![];
+    if let Some(ranges) = attrs.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+        for range in ranges.split(',') {
+            let mut bounds = range.splitn(2, '-').map(|n| n.trim().parse::<usize>());
+            match (bounds.next(), bounds.next()) {
+                (Some(Ok(begin)), None) => lines.push(begin..=begin),
+                (Some(Ok(begin)), Some(Ok(end))) => lines.push(begin.min(end)..=begin.max(end)),
+                _ => {}
+            }
+        }
+    }
+    (lang, lines)
+}
+
+/// Split highlighted HTML into lines, where spans across lines are closed at the end of
+/// each line, and reopened at the beginning of the next, so that lines can be wrapped.
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut open_tags: Vec<&str> = vec![];
+    for line in LinesWithEndings::from(html) {
+        let mut out = open_tags.concat();
+        let mut rest = line;
+        while let Some(begin) = rest.find('<') {
+            let end = rest[begin..]
+                .find('>')
+                .map_or(rest.len(), |pos| begin + pos + 1);
+            let tag = &rest[begin..end];
+            if tag.starts_with("</") {
+                open_tags.pop();
+            } else {
+                open_tags.push(tag);
+            }
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        }
+        out.push_str(rest);
+        out.push_str(&"</span>".repeat(open_tags.len()));
+        lines.push(out);
+    }
+    // closing tags after the last newline make an empty line
+    if lines.len() > 1 && !lines.last().unwrap().ends_with('\n') && !has_text(lines.last().unwrap())
+    {
+        lines.pop();
+    }
+    lines
+}
+
+fn has_text(html: &str) -> bool {
+    html.split('<').any(|s| {
+        !s.trim_start_matches(|c| c != '>')
+            .trim_start_matches('>')
+            .is_empty()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_info() {
+        assert_eq!(parse_info(""), ("", vec![]));
+        assert_eq!(parse_info("rust"), ("rust", vec![]));
+        assert_eq!(parse_info("rust {3,5-7}"), ("rust", vec![3..=3, 5..=7]));
+        assert_eq!(parse_info("c++{ 1 }"), ("c++", vec![1..=1]));
+        assert_eq!(parse_info("rust {7-5}"), ("rust", vec![5..=7]));
+        assert_eq!(
+            parse_info("rust {1-4000000000}"),
+            ("rust", vec![1..=4_000_000_000])
+        );
+    }
+
+    #[test]
+    fn test_split_lines() {
+        let html = "<span class=\"a\">x\n<span class=\"b\">y</span>\nz</span>\n";
+        assert_eq!(
+            split_lines(html),
+            vec![
+                "<span class=\"a\">x\n</span>",
+                "<span class=\"a\"><span class=\"b\">y</span>\n</span>",
+                "<span class=\"a\">z</span>\n",
+            ]
+        );
+        assert_eq!(split_lines("<a>x\n</a>").len(), 1);
+    }
+
+    #[test]
+    fn test_highlight_code_block() {
+        let code = "fn main() {\n    println!(\"<hi>\");\n}\n";
+        let config = HighlightConfig {
+            enabled: true,
+            css_classes: true,
+            line_numbers: true,
+            ..HighlightConfig::default()
+        };
+        let html = highlight_code_block("rust {2}", code, &config).unwrap();
+        assert!(html.starts_with(r#"<pre class="highlight hl-code"><code class="language-rust">"#));
+        assert!(html.contains(r#"<span class="line"><span class="line-number">1</span>"#));
+        assert!(
+            html.contains(r#"<span class="line highlighted"><span class="line-number">2</span>"#)
+        );
+        assert!(html.contains(r#"<span class="hl-storage hl-type hl-function hl-rust">fn</span>"#));
+        assert!(html.contains("&lt;hi&gt;"));
+        assert_eq!(
+            html.matches("<span").count(),
+            html.matches("</span>").count()
+        );
+
+        let config = HighlightConfig {
+            theme: "no such theme".to_string(),
+            ..config
+        };
+        assert!(highlight_code_block("rust", code, &config).is_err());
+
+        let css = highlight_css("InspiredGitHub").unwrap();
+        assert!(css.contains(".hl-code {"));
+        assert!(css.contains(".highlight .highlighted {"));
+    }
+}
@@ -11,8 +11,41 @@ mod ser;
 mod web;
 pub use crate::web::*;
 
+mod build;
+pub use crate::build::*;
+
 mod core;
 pub use crate::core::*;
 
+mod feed;
+pub use crate::feed::*;
+
+mod markdown;
+pub use crate::markdown::*;
+
+mod highlight;
+pub use crate::highlight::*;
+
+mod summary;
+pub use crate::summary::*;
+
+mod search;
+pub use crate::search::*;
+
+mod taxonomy;
+pub use crate::taxonomy::*;
+
+mod permalink;
+pub use crate::permalink::*;
+
+mod sitemap;
+pub use crate::sitemap::*;
+
+mod content;
+pub use crate::content::*;
+
+mod reload;
+use crate::reload::*;
+
 mod error;
 pub use crate::error::*;
@@ -1,17 +1,223 @@
+use std::{path::PathBuf, process};
+
+use chrono::Utc;
 use env_logger::Env;
 use pressure::*;
+use structopt::StructOpt;
+
+/// The command failed.
+const EXIT_FAILURE: i32 = 1;
+/// The instance could not be loaded.
+const EXIT_BAD_INSTANCE: i32 = 2;
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "A counterpart of PurePress in Rust.")]
+struct Opt {
+    /// Root folder of the Pressure instance [default: current directory]
+    #[structopt(
+        short,
+        long,
+        global = true,
+        env = "PRESSURE_INSTANCE",
+        parse(from_os_str)
+    )]
+    instance: Option<PathBuf>,
+
+    /// Print debug logs
+    #[structopt(short, long, global = true)]
+    verbose: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Serve the instance as a web app (default)
+    Serve {
+        /// Host to listen on
+        #[structopt(long, default_value = "127.0.0.1")]
+        host: String,
+        /// Port to listen on
+        #[structopt(short, long, default_value = "8080")]
+        port: u16,
+        /// Development mode: reload templates and pressure.toml on change, and live-reload browser tabs
+        #[structopt(long)]
+        dev: bool,
+        /// Include drafts
+        #[structopt(long)]
+        drafts: bool,
+    },
+    /// Build the instance into a static site
+    Build {
+        /// Output folder [default: build folder of the instance]
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Replace the output folder even if it's not built by Pressure
+        #[structopt(long)]
+        clean: bool,
+    },
+    /// Create a new post or page
+    New(NewCommand),
+    /// Check posts, pages and templates for errors
+    Check,
+    /// List posts, drafts, scheduled posts or pages
+    List {
+        /// What to list
+        #[structopt(
+            possible_values = &["posts", "drafts", "scheduled", "pages"],
+            default_value = "posts"
+        )]
+        kind: String,
+    },
+    /// Print the stylesheet of a syntax highlighting theme, for code highlighted with CSS classes
+    HighlightCss {
+        /// Name of the theme [default: theme in pressure.toml]
+        #[structopt(short, long)]
+        theme: Option<String>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum NewCommand {
+    /// Create a new post
+    Post {
+        /// Title of the post
+        title: String,
+        /// Create the post in the drafts folder
+        #[structopt(long)]
+        draft: bool,
+    },
+    /// Create a new page
+    Page {
+        /// Path of the page, relative to the pages folder, e.g. about or foo/bar
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        /// Title of the page [default: derived from the path]
+        #[structopt(short, long)]
+        title: Option<String>,
+    },
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    let default_filter = if opt.verbose { "debug" } else { "info" };
+    env_logger::Builder::from_env(Env::default().default_filter_or(default_filter)).init();
+
+    let root_folder = opt
+        .instance
+        .unwrap_or_else(|| std::env::current_dir().unwrap());
+    let instance = match Instance::new(&root_folder) {
+        Ok(instance) => instance,
+        Err(err) => {
+            eprintln!(
+                "Failed to load instance at {}: {}",
+                root_folder.display(),
+                err
+            );
+            process::exit(EXIT_BAD_INSTANCE);
+        }
+    };
+
+    let command = opt.command.unwrap_or(Command::Serve {
+        host: "127.0.0.1".to_string(),
+        port: 8080,
+        dev: false,
+        drafts: false,
+    });
+    if let Err(err) = run(instance, command) {
+        eprintln!("Error: {}", err);
+        process::exit(EXIT_FAILURE);
+    }
+}
 
-fn main() -> PressResult<()> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
-
-    let instance = Instance::new(
-        std::env::var("PRESSURE_INSTANCE").unwrap_or(
-            std::env::current_dir()
-                .unwrap()
-                .into_os_string()
-                .into_string()
-                .unwrap(),
-        ),
-    )?;
-    serve(instance, "127.0.0.1", 8080)
+fn run(mut instance: Instance, command: Command) -> PressResult<()> {
+    match command {
+        Command::Serve {
+            host,
+            port,
+            dev,
+            drafts,
+        } => {
+            instance.include_drafts = drafts;
+            serve(instance, &host, port, dev)
+        }
+        Command::Build { output, clean } => {
+            let output_folder = output.unwrap_or_else(|| instance.root_folder.join("build"));
+            build(instance, &output_folder, clean)?;
+            println!("Built into {}", output_folder.display());
+            Ok(())
+        }
+        Command::New(NewCommand::Post { title, draft }) => {
+            println!("Created {}", instance.new_post(&title, draft)?.display());
+            Ok(())
+        }
+        Command::New(NewCommand::Page { path, title }) => {
+            println!(
+                "Created {}",
+                instance.new_page(path, title.as_deref())?.display()
+            );
+            Ok(())
+        }
+        Command::Check => {
+            let mut error_count = 0;
+            if let Err(err) = check_templates(&instance) {
+                eprintln!("{}: {}", instance.template_folder.display(), err);
+                error_count += 1;
+            }
+            for (filepath, err) in instance.check_entries()? {
+                eprintln!("{}: {}", filepath.display(), err);
+                error_count += 1;
+            }
+            if error_count > 0 {
+                return Err(PressError::new(&format!("{} problem(s) found", error_count)));
+            }
+            println!("No problems found");
+            Ok(())
+        }
+        Command::List { kind } => {
+            if kind == "pages" {
+                for rel_path in instance.list_pages()? {
+                    let entry = instance.load_page(rel_path.with_extension(""))?;
+                    println!(
+                        "{}\t{}",
+                        rel_path.display(),
+                        entry.meta["title"].as_str().unwrap_or_default()
+                    );
+                }
+            } else {
+                // load all posts, and pick the ones of the given kind
+                instance.include_drafts = true;
+                let now = Utc::now();
+                for entry in instance.load_posts(true)? {
+                    let picked = match kind.as_str() {
+                        "drafts" => entry.is_draft(),
+                        "scheduled" => {
+                            !entry.is_draft() && entry.created.map(|dt| dt > now) == Some(true)
+                        }
+                        _ => entry.is_published(now),
+                    };
+                    if !picked {
+                        continue;
+                    }
+                    println!(
+                        "{}\t{}\t{}",
+                        entry
+                            .created
+                            .map(|dt| dt.format("%Y-%m-%d").to_string())
+                            .unwrap_or_default(),
+                        entry.meta["title"].as_str().unwrap_or_default(),
+                        entry.filepath.file_name().unwrap().to_str().unwrap()
+                    );
+                }
+            }
+            Ok(())
+        }
+        Command::HighlightCss { theme } => {
+            let theme = theme.unwrap_or_else(|| instance.markdown.highlight.theme.clone());
+            print!("{}", highlight_css(&theme)?);
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,329 @@
+//! This module handles Markdown rendering.
+
+use std::collections::HashSet;
+
+use comrak::{
+    format_html, nodes::NodeValue, parse_document, Arena, ComrakExtensionOptions, ComrakOptions,
+    ComrakParseOptions, ComrakRenderOptions,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use yaml_rust::Yaml;
+
+use crate::{
+    escape_xml, highlight_code_block, ser::SerYaml, HighlightConfig, PressError, PressResult,
+};
+
+/// Options of Markdown rendering, given in the `[markdown]` section of `pressure.toml`,
+/// and overridable by the `markdown` field of front matter.
+/// They map to the extension, parse and render options of comrak, and are all off by default.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarkdownConfig {
+    pub strikethrough: bool,
+    pub tagfilter: bool,
+    pub table: bool,
+    pub autolink: bool,
+    pub tasklist: bool,
+    pub superscript: bool,
+    /// Prefix of anchor IDs of headings. Unlike comrak, headings always get anchors,
+    /// which the table of contents links to.
+    pub header_ids: Option<String>,
+    pub footnotes: bool,
+    pub description_lists: bool,
+    pub smart: bool,
+    pub default_info_string: Option<String>,
+    pub hardbreaks: bool,
+    pub github_pre_lang: bool,
+    pub width: usize,
+    /// Keep raw HTML and dangerous links, instead of stripping them.
+    #[serde(rename = "unsafe")]
+    pub unsafe_: bool,
+    pub escape: bool,
+    pub highlight: HighlightConfig,
+}
+
+impl MarkdownConfig {
+    /// Apply the overrides in the `markdown` field of front matter, if any.
+    pub fn with_overrides(&self, meta: &Yaml) -> PressResult<MarkdownConfig> {
+        match &meta["markdown"] {
+            Yaml::Hash(_) => {}
+            Yaml::BadValue => return Ok(self.clone()),
+            _ => return Err(PressError::new("`markdown` should be a hash map")),
+        };
+        let bad_options =
+            |err: serde_json::Error| PressError::new(&format!("Bad Markdown options: {}", err));
+        let mut config = serde_json::to_value(self).map_err(bad_options)?;
+        merge_json(
+            &mut config,
+            serde_json::to_value(SerYaml(&meta["markdown"])).map_err(bad_options)?,
+        );
+        serde_json::from_value(config).map_err(bad_options)
+    }
+
+    pub fn to_comrak_options(&self) -> ComrakOptions {
+        ComrakOptions {
+            extension: ComrakExtensionOptions {
+                strikethrough: self.strikethrough,
+                tagfilter: self.tagfilter,
+                table: self.table,
+                autolink: self.autolink,
+                tasklist: self.tasklist,
+                superscript: self.superscript,
+                header_ids: self.header_ids.clone(),
+                footnotes: self.footnotes,
+                description_lists: self.description_lists,
+            },
+            parse: ComrakParseOptions {
+                smart: self.smart,
+                default_info_string: self.default_info_string.clone(),
+            },
+            render: ComrakRenderOptions {
+                hardbreaks: self.hardbreaks,
+                github_pre_lang: self.github_pre_lang,
+                width: self.width,
+                unsafe_: self.unsafe_,
+                escape: self.escape,
+            },
+        }
+    }
+}
+
+/// An item in the table of contents of an entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TocItem {
+    /// Level of the heading, from 1 to 6.
+    pub level: u32,
+    /// Plain text of the heading.
+    pub text: String,
+    /// Anchor ID of the heading.
+    pub id: String,
+}
+
+/// Merge overrides into a JSON value, recursively for objects.
+fn merge_json(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, val) in overrides {
+                merge_json(base.entry(key).or_insert(Value::Null), val);
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Render Markdown into HTML, along with the table of contents.
+/// Headings get anchors like `<a href="#id" aria-hidden="true" class="anchor" id="id"></a>`.
+pub(crate) fn render_markdown(
+    md: &str,
+    config: &MarkdownConfig,
+) -> PressResult<(String, Vec<TocItem>)> {
+    let mut options = config.to_comrak_options();
+    // anchors are generated along with the table of contents, instead of by comrak
+    options.extension.header_ids = None;
+
+    // replace code blocks with placeholders, and put highlighted HTML back after formatting,
+    // so that it's neither escaped nor stripped as raw HTML
+    let placeholder = |i: usize| format!("\u{fffc}{}\u{fffc}", i);
+    let arena = Arena::new();
+    let root = parse_document(&arena, md, &options);
+    let mut code_blocks = vec![];
+    let mut headings = vec![];
+    for node in root.descendants() {
+        let mut ast = node.data.borrow_mut();
+        match &ast.value {
+            NodeValue::CodeBlock(code_block) if config.highlight.enabled => {
+                code_blocks.push(highlight_code_block(
+                    &String::from_utf8_lossy(code_block.info.as_ref()),
+                    &String::from_utf8_lossy(code_block.literal.as_ref()),
+                    &config.highlight,
+                )?);
+                ast.value = NodeValue::Text(placeholder(code_blocks.len() - 1).into());
+            }
+            NodeValue::Heading(_) => headings.push(node),
+            _ => {}
+        }
+    }
+
+    let prefix = config.header_ids.as_deref().unwrap_or_default();
+    let mut used_ids = HashSet::new();
+    let mut items = vec![];
+    for heading in headings {
+        // the text and the level are taken from the HTML, e.g. <h2>Foo <code>bar</code></h2>
+        let mut heading_html = vec![];
+        format_html(heading, &options, &mut heading_html)?;
+        let heading_html = String::from_utf8(heading_html).unwrap();
+        let text = html_to_text(&heading_html).trim().to_string();
+        let id = unique_slug(&text, &mut used_ids);
+        let item = TocItem {
+            level: heading_html[2..3].parse().unwrap_or(1),
+            text,
+            id: format!("{}{}", prefix, id),
+        };
+        items.push((heading_html, item));
+    }
+
+    let mut html = vec![];
+    format_html(root, &options, &mut html)?;
+    let html = String::from_utf8(html).unwrap();
+
+    // headings appear in the document in order, insert anchors after their opening tags,
+    // and leave the ones not found out of the table of contents
+    let mut out = String::with_capacity(html.len());
+    let mut pos = 0;
+    let mut toc = vec![];
+    for (heading_html, item) in items {
+        if let Some(found) = html[pos..].find(heading_html.trim_end()) {
+            let tag_end = pos + found + "<hN>".len();
+            out.push_str(&html[pos..tag_end]);
+            out.push_str(&format!(
+                r##"<a href="#{0}" aria-hidden="true" class="anchor" id="{0}"></a>"##,
+                escape_xml(&item.id)
+            ));
+            pos = tag_end;
+            toc.push(item);
+        }
+    }
+    out.push_str(&html[pos..]);
+
+    for (i, code_block) in code_blocks.iter().enumerate() {
+        out = out.replace(&placeholder(i), code_block);
+    }
+    Ok((out, toc))
+}
+
+/// Make a slug out of text, keeping letters and digits of any language,
+/// e.g. `Hello, World!` -> `hello-world`, `喵 喵` -> `喵-喵`.
+pub(crate) fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() || c == '_' {
+            slug.push(c);
+        } else if (c.is_whitespace() || c == '-') && !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Make a slug that is not used yet, by appending a number if needed.
+fn unique_slug(text: &str, used: &mut HashSet<String>) -> String {
+    let mut slug = slugify(text);
+    if slug.is_empty() {
+        slug = "section".to_string();
+    }
+    let mut unique = slug.clone();
+    let mut n = 1;
+    while used.contains(&unique) {
+        unique = format!("{}-{}", slug, n);
+        n += 1;
+    }
+    used.insert(unique.clone());
+    unique
+}
+
+/// Strip tags from HTML, and unescape the entities escaped by comrak.
+pub(crate) fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlLoader;
+
+    use super::*;
+
+    #[test]
+    fn test_markdown_config() {
+        let md = "| a |\n|---|\n| b |\n\n~~c~~ <b>d</b>";
+        let config = MarkdownConfig::default();
+        let (html, _) = render_markdown(md, &config).unwrap();
+        assert!(!html.contains("<table>"));
+        assert!(!html.contains("<del>"));
+        assert!(!html.contains("<b>"));
+
+        let config: MarkdownConfig =
+            toml::from_str("table = true\nstrikethrough = true\nunsafe = true").unwrap();
+        let (html, _) = render_markdown(md, &config).unwrap();
+        assert!(html.contains("<table>"));
+        assert!(html.contains("<del>c</del>"));
+        assert!(html.contains("<b>d</b>"));
+
+        let meta = YamlLoader::load_from_str("markdown:\n  table: false\n  header_ids: h-")
+            .unwrap()
+            .remove(0);
+        let overridden = config.with_overrides(&meta).unwrap();
+        assert!(!overridden.table);
+        assert!(overridden.strikethrough);
+        assert_eq!(overridden.header_ids.as_deref(), Some("h-"));
+
+        let meta = YamlLoader::load_from_str("markdown: {tabel: true}")
+            .unwrap()
+            .remove(0);
+        assert!(config.with_overrides(&meta).is_err());
+        assert!(toml::from_str::<MarkdownConfig>("tabel = true").is_err());
+    }
+
+    #[test]
+    fn test_highlight() {
+        let md = "```rust {1}\nlet a = \"<b>\";\n```\n\n```\nplain\n```\n\n    indented\n";
+        let config: MarkdownConfig =
+            toml::from_str("[highlight]\nenabled = true\ncss_classes = true").unwrap();
+        let (html, _) = render_markdown(md, &config).unwrap();
+        assert!(html.contains(r#"<code class="language-rust"><span class="line highlighted">"#));
+        assert!(html.contains("&lt;b&gt;"));
+        assert_eq!(
+            html.matches(r#"<pre class="highlight hl-code">"#).count(),
+            3
+        );
+        assert!(!html.contains('\u{fffc}'));
+
+        let meta = YamlLoader::load_from_str("markdown: {highlight: {line_numbers: true}}")
+            .unwrap()
+            .remove(0);
+        let overridden = config.with_overrides(&meta).unwrap();
+        assert!(overridden.highlight.enabled);
+        assert!(overridden.highlight.line_numbers);
+    }
+
+    #[test]
+    fn test_toc() {
+        let md = "# Hello, World!\n\n## 喵\n\n## 喵\n\n### `<code>` & more\n\ntext\n\n## ???";
+        let (html, toc) = render_markdown(md, &MarkdownConfig::default()).unwrap();
+        let ids: Vec<&str> = toc.iter().map(|item| item.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec!["hello-world", "喵", "喵-1", "code-more", "section"]
+        );
+        let levels: Vec<u32> = toc.iter().map(|item| item.level).collect();
+        assert_eq!(levels, vec![1, 2, 2, 3, 2]);
+        assert_eq!(toc[3].text, "<code> & more");
+        assert!(html.contains(
+            r##"<h2><a href="#喵-1" aria-hidden="true" class="anchor" id="喵-1"></a>喵</h2>"##
+        ));
+
+        let config: MarkdownConfig = toml::from_str("header_ids = \"h-\"").unwrap();
+        let (html, toc) = render_markdown("# A\n# A", &config).unwrap();
+        assert_eq!(toc[1].id, "h-a-1");
+        assert_eq!(html.matches(r#"class="anchor""#).count(), 2);
+
+        let config: MarkdownConfig = toml::from_str(r#"header_ids = '"><'"#).unwrap();
+        let (html, toc) = render_markdown("# A", &config).unwrap();
+        assert_eq!(toc[0].id, "\"><a");
+        assert!(html.contains(r#"id="&quot;&gt;&lt;a""#));
+    }
+}
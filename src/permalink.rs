@@ -0,0 +1,436 @@
+//! This module handles permalinks of posts and pages.
+
+use std::path::Path;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::{Deserialize, Serialize};
+use yaml_rust::Yaml;
+
+use crate::{Entry, PressError, PressResult, Taxonomy, RESERVED_PATHS};
+
+/// Characters to escape in values of placeholders, where slashes are kept,
+/// since a hierarchical term or the path of a page may span several segments.
+pub(crate) const PATH_ESCAPES: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// First segments of URLs taken by other routes, besides those reserved for taxonomies.
+const RESERVED_ROOTS: [&str; 7] = [
+    "__pressure",
+    "atom.xml",
+    "rss.xml",
+    "feed.json",
+    "sitemap.xml",
+    "search-index.json",
+    "robots.txt",
+];
+
+/// Term given to placeholders of taxonomies in permalinks of posts without terms.
+const NO_TERM: &str = "uncategorized";
+
+/// Templates of URLs of entries, given in the `[permalinks]` section of `pressure.toml`,
+/// and overridable by the `permalink` field of front matter.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PermalinkConfig {
+    /// Template of URLs of posts, e.g. `/{year}/{slug}.html`.
+    pub post: String,
+    /// Template of URLs of pages, e.g. `/{path}/`, where pages are at their paths by default,
+    /// i.e. `/{path}.html`, or `/{path}/` for `index.md`.
+    pub page: Option<String>,
+}
+
+impl Default for PermalinkConfig {
+    fn default() -> Self {
+        Self {
+            post: "/post/{year}/{month}/{day}/{slug}/".to_string(),
+            page: None,
+        }
+    }
+}
+
+impl PermalinkConfig {
+    /// Check that the templates are well-formed, that they only use known placeholders,
+    /// and that they don't start with segments taken by other routes.
+    pub(crate) fn validate(&self, taxonomies: &[Taxonomy]) -> PressResult<()> {
+        if !self.post.contains("{slug}") {
+            return Err(bad_permalink(&self.post, "should contain `{slug}`"));
+        }
+        let url = expand_permalink(&self.post, |name| {
+            post_placeholder_pattern(name, taxonomies).map(|_| "".to_string())
+        })?;
+        check_root(&self.post, &url, taxonomies)?;
+        if let Some(page) = &self.page {
+            if !page.contains("{path}") && !page.contains("{slug}") {
+                return Err(bad_permalink(page, "should contain `{path}` or `{slug}`"));
+            }
+            let url = expand_permalink(page, |name| match name {
+                "path" | "slug" => Some("".to_string()),
+                _ => None,
+            })?;
+            check_root(page, &url, taxonomies)?;
+        }
+        Ok(())
+    }
+
+    /// Pattern of the route of posts, for generating URLs by `url_for`,
+    /// e.g. `/{year}/{slug}.html` -> `/{year:\d{4}}/{slug}.html`.
+    pub(crate) fn post_route(&self, taxonomies: &[Taxonomy]) -> String {
+        let mut pattern = String::new();
+        for part in parse_template(&self.post).unwrap_or_default() {
+            match part {
+                Part::Text(text) => pattern.push_str(text),
+                Part::Placeholder(name) => {
+                    match post_placeholder_pattern(name, taxonomies).flatten() {
+                        Some(regex) => pattern.push_str(&format!("{{{}:{}}}", name, regex)),
+                        None => pattern.push_str(&format!("{{{}}}", name)),
+                    }
+                }
+            }
+        }
+        pattern
+    }
+
+    /// Generate the URL of a post, given the date and the name in its file name.
+    /// Placeholders of taxonomies, e.g. `{category}`, are given the slug of the first term.
+    pub(crate) fn post_url(
+        &self,
+        post: &Entry,
+        (year, month, day): (u16, u8, u8),
+        name: &str,
+        taxonomies: &[Taxonomy],
+    ) -> PressResult<String> {
+        let slug = entry_slug(&post.meta)?.unwrap_or(name);
+        let template = entry_permalink(&post.meta)?.unwrap_or(&self.post);
+        let url = expand_permalink(template, |placeholder| match placeholder {
+            "year" => Some(format!("{:04}", year)),
+            "month" => Some(format!("{:02}", month)),
+            "day" => Some(format!("{:02}", day)),
+            "slug" => Some(slug.to_string()),
+            _ => {
+                let taxonomy = taxonomies.iter().find(|t| t.singular() == placeholder)?;
+                Some(match taxonomy.terms(&post.meta).next() {
+                    Some(term) => taxonomy.slug(term),
+                    None => NO_TERM.to_string(),
+                })
+            }
+        })?;
+        check_root(template, &url, taxonomies)?;
+        Ok(url)
+    }
+
+    /// Generate the URL of a page, given its path relative to the pages folder,
+    /// or `None` for the root `index.md`, whose URL is taken by the index.
+    /// e.g. foo/bar.md -> /foo/bar.html, foo/index.md -> /foo/
+    /// Pages at their paths may be anywhere, e.g. tag/foo.md -> /tag/foo.html,
+    /// but templates must not lead them to segments taken by other routes.
+    pub(crate) fn page_url(
+        &self,
+        page: &Entry,
+        rel_path: &Path,
+        taxonomies: &[Taxonomy],
+    ) -> PressResult<Option<String>> {
+        let is_index = rel_path.file_name().unwrap_or_default() == "index.md";
+        let template = match (entry_permalink(&page.meta)?, &self.page) {
+            (Some(permalink), _) => permalink,
+            (None, _) if is_index && rel_path.parent() == Some(Path::new("")) => return Ok(None),
+            (None, Some(template)) => template,
+            (None, None) if is_index => "/{path}/",
+            (None, None) => "/{path}.html",
+        };
+        // the slug of an index page is the name of its folder
+        let folder = if is_index {
+            rel_path.parent().unwrap_or(rel_path)
+        } else {
+            rel_path.parent().unwrap_or_else(|| Path::new(""))
+        };
+        let default_slug = if is_index {
+            folder.file_name().unwrap_or_default()
+        } else {
+            rel_path.file_stem().unwrap_or_default()
+        };
+        let slug = match entry_slug(&page.meta)? {
+            Some(slug) => slug.to_string(),
+            None => default_slug.to_string_lossy().to_string(),
+        };
+        let mut segments: Vec<String> = folder
+            .iter()
+            .map(|comp| comp.to_string_lossy().to_string())
+            .collect();
+        if is_index {
+            segments.pop();
+        }
+        segments.push(slug.clone());
+        let path = segments.join("/");
+        let url = expand_permalink(template, |placeholder| match placeholder {
+            "path" => Some(path.clone()),
+            "slug" => Some(slug.clone()),
+            _ => None,
+        })?;
+        if entry_permalink(&page.meta)?.is_some() || self.page.is_some() {
+            check_root(template, &url, taxonomies)?;
+        }
+        Ok(Some(url))
+    }
+}
+
+/// Pattern of a placeholder in the route of posts, or `None` if unknown.
+/// A known placeholder may have no pattern of its own, i.e. a single segment.
+fn post_placeholder_pattern(name: &str, taxonomies: &[Taxonomy]) -> Option<Option<&'static str>> {
+    match name {
+        "year" => Some(Some(r"\d{4}")),
+        "month" | "day" => Some(Some(r"\d{2}")),
+        "slug" => Some(None),
+        _ => {
+            let taxonomy = taxonomies.iter().find(|t| t.singular() == name)?;
+            Some(if taxonomy.hierarchical {
+                Some(".+")
+            } else {
+                None
+            })
+        }
+    }
+}
+
+/// Check that a URL generated by a template doesn't start with a segment taken by other routes,
+/// e.g. `/tag/foo/` or `/archive/foo/`, or the entry would never be found.
+fn check_root(template: &str, url: &str, taxonomies: &[Taxonomy]) -> PressResult<()> {
+    let root = url.trim_start_matches('/').split('/').next().unwrap_or_default();
+    // posts used to be under `/post/`, which is no route of its own any more
+    let reserved = root != "post"
+        && (RESERVED_PATHS.contains(&root)
+            || RESERVED_ROOTS.contains(&root)
+            || taxonomies.iter().any(|t| t.name == root || t.singular() == root));
+    if reserved {
+        return Err(bad_permalink(
+            template,
+            &format!("`/{}` is taken by other routes", root),
+        ));
+    }
+    Ok(())
+}
+
+/// The `permalink` field of front matter, if any.
+fn entry_permalink(meta: &Yaml) -> PressResult<Option<&str>> {
+    match &meta["permalink"] {
+        Yaml::String(permalink) => Ok(Some(permalink)),
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        _ => Err(PressError::new("`permalink` should be a string")),
+    }
+}
+
+/// The `slug` field of front matter, if any, which is a single segment of URLs.
+fn entry_slug(meta: &Yaml) -> PressResult<Option<&str>> {
+    match &meta["slug"] {
+        Yaml::String(slug) if !slug.trim().is_empty() => match slug.trim() {
+            "." | ".." => Err(PressError::new("`slug` should not be `.` or `..`")),
+            slug if slug.contains('/') => Err(PressError::new("`slug` should not contain `/`")),
+            slug => Ok(Some(slug)),
+        },
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        _ => Err(PressError::new("`slug` should be a non-empty string")),
+    }
+}
+
+/// Whether a path has `.` or `..` segments, which would lead URLs elsewhere.
+fn has_dot_segments(path: &str) -> bool {
+    path.split('/').any(|segment| segment == "." || segment == "..")
+}
+
+enum Part<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Split a template into text and placeholders like `{year}`.
+fn parse_template(template: &str) -> PressResult<Vec<Part<'_>>> {
+    if !template.starts_with('/') {
+        return Err(bad_permalink(template, "should start with `/`"));
+    }
+    if has_dot_segments(template) {
+        return Err(bad_permalink(template, "should not contain `.` or `..` segments"));
+    }
+    let mut parts = vec![];
+    let mut rest = template;
+    while let Some(begin) = rest.find(&['{', '}'][..]) {
+        if rest[begin..].starts_with('}') {
+            return Err(bad_permalink(template, "unmatched `}`"));
+        }
+        let end = match rest[begin..].find('}') {
+            Some(end) => begin + end,
+            None => return Err(bad_permalink(template, "unmatched `{`")),
+        };
+        parts.push(Part::Text(&rest[..begin]));
+        parts.push(Part::Placeholder(&rest[begin + 1..end]));
+        rest = &rest[end + 1..];
+    }
+    parts.push(Part::Text(rest));
+    Ok(parts)
+}
+
+/// Fill in placeholders of a template with values given by `value`, which returns `None` for
+/// unknown placeholders. Values are percent-encoded, except for slashes,
+/// and must not have `.` or `..` segments.
+pub(crate) fn expand_permalink<F>(template: &str, value: F) -> PressResult<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut url = String::new();
+    for part in parse_template(template)? {
+        match part {
+            Part::Text(text) => url.push_str(text),
+            Part::Placeholder(name) => match value(name) {
+                Some(value) if has_dot_segments(&value) => {
+                    return Err(bad_permalink(
+                        template,
+                        &format!("`{{{}}}` should not be `.` or `..`", name),
+                    ))
+                }
+                Some(value) => url.push_str(&utf8_percent_encode(&value, PATH_ESCAPES).to_string()),
+                None => {
+                    return Err(bad_permalink(
+                        template,
+                        &format!("unknown placeholder `{{{}}}`", name),
+                    ))
+                }
+            },
+        }
+    }
+    Ok(url)
+}
+
+fn bad_permalink(template: &str, reason: &str) -> PressError {
+    PressError::new(&format!("Bad permalink `{}`: {}", template, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlLoader;
+
+    use super::*;
+    use crate::{default_taxonomies, normalize_terms};
+
+    fn entry(front_matter: &str) -> Entry {
+        let mut meta = YamlLoader::load_from_str(front_matter)
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| Yaml::Hash(Default::default()));
+        for taxonomy in &default_taxonomies() {
+            normalize_terms(&mut meta, taxonomy);
+        }
+        Entry {
+            meta,
+            ..Entry::default()
+        }
+    }
+
+    #[test]
+    fn test_post_url() {
+        let taxonomies = default_taxonomies();
+        let mut config = PermalinkConfig::default();
+        let date = (2020, 8, 31);
+        let post = entry("categories: Dev / C++");
+        assert_eq!(
+            config.post_url(&post, date, "test", &taxonomies).unwrap(),
+            "/post/2020/08/31/test/"
+        );
+        assert_eq!(
+            config.post_route(&taxonomies),
+            r"/post/{year:\d{4}}/{month:\d{2}}/{day:\d{2}}/{slug}/"
+        );
+
+        config.post = "/blog/{category}/{slug}.html".to_string();
+        assert_eq!(
+            config.post_url(&post, date, "test", &taxonomies).unwrap(),
+            "/blog/dev/c-plus-plus/test.html"
+        );
+        assert_eq!(config.post_route(&taxonomies), "/blog/{category:.+}/{slug}.html");
+        let post = entry("slug: hello world");
+        assert_eq!(
+            config.post_url(&post, date, "test", &taxonomies).unwrap(),
+            "/blog/uncategorized/hello%20world.html"
+        );
+        let post = entry("permalink: /{year}/about-{slug}/\nslug: 你好");
+        assert_eq!(
+            config.post_url(&post, date, "test", &taxonomies).unwrap(),
+            "/2020/about-%E4%BD%A0%E5%A5%BD/"
+        );
+        let post = entry("permalink: /{author}/");
+        assert!(config.post_url(&post, date, "test", &taxonomies).is_err());
+        for bad in &["permalink: /tag/{slug}/", "permalink: /archive/{slug}/", "slug: tags"] {
+            let mut config = config.clone();
+            config.post = "/{slug}/".to_string();
+            assert!(config.post_url(&entry(bad), date, "test", &taxonomies).is_err());
+        }
+        for bad in &["slug: ../../x", "slug: ..", "permalink: /../../{slug}/"] {
+            assert!(config.post_url(&entry(bad), date, "test", &taxonomies).is_err());
+        }
+        assert!(config.post_url(&entry(""), date, "..", &taxonomies).is_err());
+    }
+
+    #[test]
+    fn test_page_url() {
+        let taxonomies = default_taxonomies();
+        let mut config = PermalinkConfig::default();
+        let page = entry("");
+        let page_url = |config: &PermalinkConfig, page: &Entry, rel_path: &str| {
+            config.page_url(page, Path::new(rel_path), &taxonomies)
+        };
+        let url = |config: &PermalinkConfig, page: &Entry, rel_path: &str| {
+            page_url(config, page, rel_path).unwrap()
+        };
+        assert_eq!(url(&config, &page, "foo/bar.md").unwrap(), "/foo/bar.html");
+        assert_eq!(url(&config, &page, "foo/index.md").unwrap(), "/foo/");
+        assert_eq!(url(&config, &page, "index.md"), None);
+        assert_eq!(url(&config, &page, "tag/foo.md").unwrap(), "/tag/foo.html");
+
+        config.page = Some("/{path}/".to_string());
+        assert_eq!(url(&config, &page, "foo/bar.md").unwrap(), "/foo/bar/");
+        assert_eq!(url(&config, &page, "foo/index.md").unwrap(), "/foo/");
+        let page = entry("slug: baz");
+        assert_eq!(url(&config, &page, "foo/bar.md").unwrap(), "/foo/baz/");
+        assert_eq!(url(&config, &page, "foo/index.md").unwrap(), "/baz/");
+        let page = entry("permalink: /about/");
+        assert_eq!(url(&config, &page, "index.md").unwrap(), "/about/");
+        let page = entry("slug: a/b");
+        assert!(page_url(&config, &page, "foo.md").is_err());
+        let page = entry("permalink: /a/./{slug}/");
+        assert!(page_url(&config, &page, "foo.md").is_err());
+        assert!(page_url(&config, &entry(""), "tag/foo.md").is_err());
+        assert!(page_url(&config, &entry("permalink: /sitemap.xml"), "foo.md").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let taxonomies = default_taxonomies();
+        let validate = |post: &str, page: Option<&str>| {
+            PermalinkConfig {
+                post: post.to_string(),
+                page: page.map(str::to_string),
+            }
+            .validate(&taxonomies)
+        };
+        assert!(validate("/{year}/{slug}.html", None).is_ok());
+        assert!(validate("/{tag}/{slug}/", Some("/pages/{path}/")).is_ok());
+        assert!(validate("{year}/{slug}/", None).is_err());
+        assert!(validate("/{year}/", None).is_err());
+        assert!(validate("/{author}/{slug}/", None).is_err());
+        assert!(validate("/{year/{slug}/", None).is_err());
+        assert!(validate("/{slug}}/", None).is_err());
+        assert!(validate("/{slug}/", Some("/{year}/{path}/")).is_err());
+        assert!(validate("/../{slug}/", None).is_err());
+        assert!(validate("/{slug}/", Some("/{path}/")).is_ok());
+        for bad in &["/page/{slug}/", "/tag/{slug}/", "/tags/{slug}/", "/archive/{slug}/"] {
+            assert!(validate(bad, None).is_err());
+            assert!(validate("/{slug}/", Some(&bad.replace("slug", "path"))).is_err());
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! This module handles live reload of browser tabs during authoring.
+
+use std::{
+    path::Path,
+    sync::{mpsc, Arc, Mutex, RwLock},
+    thread,
+    time::Duration,
+};
+
+use actix_web::web::Bytes;
+use futures::{
+    channel::mpsc::{unbounded, UnboundedSender},
+    Stream, StreamExt,
+};
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use tera::Tera;
+
+use crate::{relative_path, Instance, PressResult};
+
+/// Browser tabs connected to the live reload event stream.
+#[derive(Debug, Default)]
+pub(crate) struct LiveReload {
+    clients: Mutex<Vec<UnboundedSender<Bytes>>>,
+}
+
+impl LiveReload {
+    /// Register a browser tab, and get the server-sent event stream for it.
+    pub(crate) fn subscribe(&self) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+        let (tx, rx) = unbounded();
+        // reconnect soon after the server restarts
+        tx.unbounded_send(Bytes::from_static(b"retry: 500\n\n"))
+            .unwrap();
+        self.clients.lock().unwrap().push(tx);
+        rx.map(Ok)
+    }
+
+    /// Tell all connected browser tabs to reload.
+    pub(crate) fn reload(&self) {
+        self.clients.lock().unwrap().retain(|tx| {
+            tx.unbounded_send(Bytes::from_static(b"data: reload\n\n"))
+                .is_ok()
+        });
+    }
+
+    /// Close all event streams, so that the browser tabs reload once they reconnect.
+    pub(crate) fn disconnect(&self) {
+        self.clients.lock().unwrap().clear();
+    }
+}
+
+/// Inject the live reload script into a rendered HTML page.
+pub(crate) fn inject_script(html: String, events_url: &str) -> String {
+    let script = format!(
+        r#"<script>
+(function () {{
+  var connected = false;
+  var source = new EventSource("{}");
+  source.onopen = function () {{
+    if (connected) location.reload();
+    connected = true;
+  }};
+  source.onmessage = function (event) {{
+    if (event.data === "reload") location.reload();
+  }};
+}})();
+</script>
+"#,
+        events_url
+    );
+    match html.rfind("</body>") {
+        Some(pos) => format!("{}{}{}", &html[..pos], script, &html[pos..]),
+        None => html + &script,
+    }
+}
+
+/// Watch templates, static files and `pressure.toml` in a background thread.
+/// Templates are re-parsed when changed, and browser tabs are told to reload.
+/// `on_config_change` is called when `pressure.toml` is changed, instead.
+/// Watching stops once the returned watcher is dropped.
+pub(crate) fn watch_site<F>(
+    instance: &Instance,
+    templates: Arc<RwLock<Tera>>,
+    live_reload: Arc<LiveReload>,
+    on_config_change: F,
+) -> PressResult<RecommendedWatcher>
+where
+    F: Fn() + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    let mut site_watcher = watcher(tx, Duration::from_millis(200))?;
+    for folder in &[
+        &instance.template_folder,
+        &instance.static_folder,
+        &instance.theme_static_folder,
+    ] {
+        if folder.is_dir() {
+            site_watcher.watch(folder, RecursiveMode::Recursive)?;
+        }
+    }
+    // editors may replace the file instead of writing to it, so watch the folder
+    site_watcher.watch(&instance.root_folder, RecursiveMode::NonRecursive)?;
+
+    let instance = instance.clone();
+    // paths of events are absolute, while the root folder may be given relative
+    let root_folder = instance.root_folder.canonicalize()?;
+    thread::spawn(move || {
+        let is_config = |path: &Path| {
+            relative_path(path, &root_folder) == Some(Path::new("pressure.toml"))
+        };
+        let in_root = |path: &Path| {
+            relative_path(path, &root_folder).map(|rel_path| rel_path.components().count())
+                == Some(1)
+        };
+        let is_template = |path: &Path| relative_path(path, &instance.template_folder).is_some();
+        // the loop ends when the watcher is dropped
+        for event in rx {
+            let paths = match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Remove(path) => vec![path],
+                DebouncedEvent::Rename(from, to) => vec![from, to],
+                _ => continue,
+            };
+            if paths.iter().any(|p| is_config(p)) {
+                log::info!("Configuration changed, restarting");
+                on_config_change();
+                continue;
+            }
+            if paths.iter().any(|p| is_template(p)) {
+                if let Err(err) = templates.write().unwrap().full_reload() {
+                    log::warn!("Failed to reload templates: {}", err);
+                    continue;
+                }
+                log::info!("Reloaded templates");
+            } else if paths.iter().all(|p| in_root(p)) {
+                // other files in the root folder are not interesting
+                continue;
+            }
+            live_reload.reload();
+        }
+    });
+    Ok(site_watcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inject_script() {
+        let html = inject_script("<html><body>Hi</body></html>".to_string(), "/events");
+        assert!(html.starts_with("<html><body>Hi<script>"));
+        assert!(html.contains(r#"new EventSource("/events")"#));
+        assert!(html.ends_with("</script>\n</body></html>"));
+
+        let html = inject_script("Hi".to_string(), "/events");
+        assert!(html.starts_with("Hi<script>"));
+    }
+}
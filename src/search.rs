@@ -0,0 +1,390 @@
+//! This module handles search index generation and searching.
+
+use std::{
+    cmp::{min, Ordering},
+    collections::{BTreeMap, BTreeSet, HashMap},
+};
+
+use serde::Serialize;
+use yaml_rust::Yaml;
+
+use crate::{escape_xml, html_to_text, is_cjk, Entry};
+
+/// Tokens in titles weigh more than those in bodies.
+const TITLE_WEIGHT: f64 = 3.0;
+/// Max number of characters in a snippet.
+const SNIPPET_CHARS: usize = 160;
+
+/// A post or page in the search index.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchDocument {
+    pub title: String,
+    /// URL path of the entry.
+    pub url: String,
+    pub tags: Vec<String>,
+    pub categories: Vec<String>,
+    /// Creation date like `2020-12-25`, empty if unknown.
+    pub date: String,
+    /// Plain text of the content.
+    pub body: String,
+    /// Distinct tokens of the title and the body, see `tokenize`.
+    pub tokens: BTreeSet<String>,
+}
+
+impl SearchDocument {
+    /// Create a search document for an entry whose URL is generated and content is loaded,
+    /// or `None` if the entry opts out by `search: false` or `noindex: true`.
+    pub fn from_entry(entry: &Entry) -> Option<SearchDocument> {
+        if entry.meta["search"] == Yaml::Boolean(false)
+            || entry.meta["noindex"] == Yaml::Boolean(true)
+        {
+            return None;
+        }
+        let terms = |key: &str| -> Vec<String> {
+            entry.meta[key]
+                .as_vec()
+                .map(|terms| {
+                    terms
+                        .iter()
+                        .filter_map(|term| term.as_str())
+                        .map(|term| term.to_string())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let title = entry.meta["title"].as_str().unwrap_or_default().to_string();
+        let body = html_to_text(&entry.content)
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let tokens = tokenize(&title)
+            .into_iter()
+            .chain(tokenize(&body))
+            .collect();
+        Some(SearchDocument {
+            url: entry.url.clone()?,
+            tags: terms("tags"),
+            categories: terms("categories"),
+            date: entry
+                .created
+                .map(|dt| dt.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+            title,
+            body,
+            tokens,
+        })
+    }
+}
+
+/// An inverted index of search documents, for searching on the server.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    documents: Vec<SearchDocument>,
+    /// Documents containing each token, sorted by token for prefix matching.
+    postings: BTreeMap<String, Vec<Posting>>,
+}
+
+/// Occurrences of a token in a document.
+#[derive(Debug, Clone, Copy)]
+struct Posting {
+    doc: usize,
+    title_count: u32,
+    body_count: u32,
+}
+
+/// A document matching a search query.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    pub tags: Vec<String>,
+    pub categories: Vec<String>,
+    pub date: String,
+    /// Part of the body around the first match, in HTML with matches in `<mark>`.
+    pub snippet: String,
+    pub score: f64,
+}
+
+impl SearchIndex {
+    pub fn new(documents: Vec<SearchDocument>) -> SearchIndex {
+        let mut postings: BTreeMap<String, Vec<Posting>> = BTreeMap::new();
+        for (doc, document) in documents.iter().enumerate() {
+            let mut counts: HashMap<String, (u32, u32)> = HashMap::new();
+            for token in tokenize(&document.title) {
+                counts.entry(token).or_default().0 += 1;
+            }
+            for token in tokenize(&document.body) {
+                counts.entry(token).or_default().1 += 1;
+            }
+            for (token, (title_count, body_count)) in counts {
+                postings.entry(token).or_default().push(Posting {
+                    doc,
+                    title_count,
+                    body_count,
+                });
+            }
+        }
+        SearchIndex {
+            documents,
+            postings,
+        }
+    }
+
+    /// Search for documents containing all tokens of the query, ranked by TF-IDF.
+    /// A token matches the tokens it's a prefix of, if it doesn't match any token exactly,
+    /// while a single CJK character matches the tokens it's in, since it's mostly in bigrams.
+    /// Return the total number of matched documents, and the results in the given range.
+    pub fn search(&self, query: &str, offset: usize, limit: usize) -> (usize, Vec<SearchResult>) {
+        let mut query_tokens = tokenize(query);
+        query_tokens.sort();
+        query_tokens.dedup();
+        if query_tokens.is_empty() {
+            return (0, vec![]);
+        }
+
+        let doc_count = self.documents.len() as f64;
+        let mut scores: HashMap<usize, (usize, f64)> = HashMap::new();
+        for token in &query_tokens {
+            let mut chars = token.chars();
+            let single_cjk = match (chars.next(), chars.next()) {
+                (Some(c), None) => is_cjk(c),
+                _ => false,
+            };
+            let matched_postings: Vec<&Vec<Posting>> = match self.postings.get(token) {
+                _ if single_cjk => self
+                    .postings
+                    .iter()
+                    .filter(|(t, _)| t.contains(token.as_str()))
+                    .map(|(_, postings)| postings)
+                    .collect(),
+                Some(postings) => vec![postings],
+                None => self
+                    .postings
+                    .range(token.clone()..)
+                    .take_while(|(t, _)| t.starts_with(token.as_str()))
+                    .map(|(_, postings)| postings)
+                    .collect(),
+            };
+            let mut token_scores: HashMap<usize, f64> = HashMap::new();
+            for postings in matched_postings {
+                let idf = (doc_count / postings.len() as f64).ln() + 1.0;
+                for posting in postings {
+                    *token_scores.entry(posting.doc).or_default() += idf
+                        * (TITLE_WEIGHT * posting.title_count as f64 + posting.body_count as f64);
+                }
+            }
+            for (doc, score) in token_scores {
+                let entry = scores.entry(doc).or_default();
+                entry.0 += 1;
+                entry.1 += score;
+            }
+        }
+
+        let mut hits: Vec<(usize, f64)> = scores
+            .into_iter()
+            .filter(|(_, (matched, _))| *matched == query_tokens.len())
+            .map(|(doc, (_, score))| (doc, score))
+            .collect();
+        // higher scores first, and newer documents first for equal scores
+        hits.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| self.documents[b.0].date.cmp(&self.documents[a.0].date))
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        let results = hits
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|&(doc, score)| {
+                let document = &self.documents[doc];
+                SearchResult {
+                    title: document.title.clone(),
+                    url: document.url.clone(),
+                    tags: document.tags.clone(),
+                    categories: document.categories.clone(),
+                    date: document.date.clone(),
+                    snippet: snippet(&document.body, &query_tokens),
+                    score,
+                }
+            })
+            .collect();
+        (hits.len(), results)
+    }
+}
+
+/// Cut the text around the first match of the tokens, with all matches highlighted.
+fn snippet(text: &str, tokens: &[String]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+    let mut marked = vec![false; chars.len()];
+    for token in tokens {
+        let token: Vec<char> = token.chars().collect();
+        if token.is_empty() || token.len() > lower.len() {
+            continue;
+        }
+        for i in 0..=lower.len() - token.len() {
+            if lower[i..i + token.len()] == token[..] {
+                marked[i..i + token.len()]
+                    .iter_mut()
+                    .for_each(|m| *m = true);
+            }
+        }
+    }
+
+    let first = marked.iter().position(|&m| m).unwrap_or(0);
+    let begin = first.saturating_sub(SNIPPET_CHARS / 4);
+    let end = min(chars.len(), begin + SNIPPET_CHARS);
+    let mut html = String::new();
+    if begin > 0 {
+        html.push('…');
+    }
+    let mut i = begin;
+    while i < end {
+        let run_end = (i..end).find(|&j| marked[j] != marked[i]).unwrap_or(end);
+        let run: String = chars[i..run_end].iter().collect();
+        if marked[i] {
+            html.push_str(&format!("<mark>{}</mark>", escape_xml(&run)));
+        } else {
+            html.push_str(&escape_xml(&run));
+        }
+        i = run_end;
+    }
+    if end < chars.len() {
+        html.push('…');
+    }
+    html
+}
+
+/// Split text into lowercase search tokens. Words written with spaces between them are
+/// tokens by themselves, while runs of CJK characters are split into overlapping bigrams,
+/// e.g. `Rust 测试文章` -> `rust`, `测试`, `试文`, `文章`.
+/// A single CJK character between other characters is a token by itself.
+pub fn tokenize(text: &str) -> Vec<String> {
+    fn flush_cjk(run: &mut Vec<char>, tokens: &mut Vec<String>) {
+        if run.len() == 1 {
+            tokens.push(run[0].to_string());
+        }
+        for pair in run.windows(2) {
+            tokens.push(pair.iter().collect());
+        }
+        run.clear();
+    }
+
+    let mut tokens = vec![];
+    let mut word = String::new();
+    let mut cjk_run: Vec<char> = vec![];
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if is_cjk(c) && c.is_alphanumeric() {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            cjk_run.push(c);
+        } else {
+            flush_cjk(&mut cjk_run, &mut tokens);
+            if c.is_alphanumeric() || c == '_' {
+                word.push(c);
+            } else if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+        }
+    }
+    flush_cjk(&mut cjk_run, &mut tokens);
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Instance;
+
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Hello, Rust 测试文章！喵 C++"),
+            vec!["hello", "rust", "测试", "试文", "文章", "喵", "c"]
+        );
+        assert_eq!(tokenize("用Rust写"), vec!["用", "rust", "写"]);
+    }
+
+    fn document(title: &str, date: &str, body: &str) -> SearchDocument {
+        SearchDocument {
+            title: title.to_string(),
+            url: format!("/{}/", title),
+            tags: vec![],
+            categories: vec![],
+            date: date.to_string(),
+            body: body.to_string(),
+            tokens: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let index = SearchIndex::new(vec![
+            document("rust", "2020-01-01", "Rust is fast. 这是一个测试文章。"),
+            document("intro", "2020-01-02", "Learn rust and Python <3"),
+            document("python", "2020-01-03", "Python is slow"),
+        ]);
+        let (count, results) = index.search("Rust", 0, 10);
+        assert_eq!(count, 2);
+        assert_eq!(results[0].title, "rust"); // matched in title
+        assert_eq!(
+            results[1].snippet,
+            "Learn <mark>rust</mark> and Python &lt;3"
+        );
+
+        let (count, results) = index.search("python", 0, 1);
+        assert_eq!(count, 2);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].title, "python");
+
+        assert_eq!(index.search("rust python", 0, 10).0, 1);
+        assert_eq!(index.search("pyth", 0, 10).0, 2);
+        let (count, results) = index.search("测试文章", 0, 10);
+        assert_eq!(count, 1);
+        assert!(results[0].snippet.ends_with("一个<mark>测试文章</mark>。"));
+        assert_eq!(index.search("文测", 0, 10).0, 0);
+        // only in the bigrams `一个` and `文章` as the second half
+        assert_eq!(index.search("个", 0, 10).0, 1);
+        assert_eq!(index.search("章", 0, 10).0, 1);
+        assert_eq!(index.search("  ", 0, 10).0, 0);
+    }
+
+    #[test]
+    fn test_snippet() {
+        let text = "a".repeat(100) + "foo" + &"b".repeat(300);
+        let html = snippet(&text, &["foo".to_string()]);
+        assert!(html.starts_with('…'));
+        assert!(html.ends_with('…'));
+        assert_eq!(
+            html.chars().count(),
+            SNIPPET_CHARS + "<mark></mark>".len() + 2
+        );
+        assert_eq!(snippet("foo", &["bar".to_string()]), "foo");
+    }
+
+    #[test]
+    fn test_search_document() {
+        let inst = Instance::new("tests/test_inst").unwrap();
+        let mut post = inst.load_post(2020, 8, 31, "test", false).unwrap();
+        let doc = SearchDocument::from_entry(&post).unwrap();
+        assert_eq!(doc.url, "/post/2020/08/31/test/");
+        assert_eq!(doc.title, "测试");
+        assert_eq!(doc.tags, vec!["C++", "奇技淫巧"]);
+        assert_eq!(doc.categories, vec!["Dev"]);
+        assert_eq!(doc.date, "2020-08-31");
+        assert_eq!(doc.body, "喵 这是一个测试文章。");
+        assert!(doc.tokens.contains("测试"));
+        assert!(doc.tokens.contains("文章"));
+        post.url = None;
+        assert!(SearchDocument::from_entry(&post).is_none());
+    }
+}
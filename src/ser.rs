@@ -1,3 +1,4 @@
+use chrono::{SecondsFormat, Utc};
 use serde::{
     ser::{SerializeMap, SerializeSeq},
     Serialize,
@@ -7,7 +8,7 @@ use yaml_rust::Yaml;
 use crate::Entry;
 
 #[derive(Debug)]
-struct SerYaml<'a>(&'a Yaml);
+pub(crate) struct SerYaml<'a>(pub(crate) &'a Yaml);
 
 impl<'a> Serialize for SerYaml<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -53,21 +54,34 @@ impl Serialize for Entry {
             Some(url) => s.serialize_entry("url", url)?,
             None => s.serialize_entry("url", "")?,
         }
-        match &self.created {
-            Some(created) => {
-                s.serialize_entry("created", &format!("{}", created.format("%Y-%m-%d")))?
-            }
-            None => s.serialize_entry("created", "")?,
-        }
-        match &self.updated {
-            Some(updated) => {
-                s.serialize_entry("updated", &format!("{}", updated.format("%Y-%m-%d")))?
+        for (key, dt) in vec![
+            ("created", &self.created),
+            ("updated", &self.updated),
+            ("expires", &self.expires),
+        ] {
+            match dt {
+                Some(dt) => {
+                    s.serialize_entry(key, &format!("{}", dt.format("%Y-%m-%d")))?;
+                    s.serialize_entry(&format!("{}_local", key), &dt.to_rfc3339())?;
+                    s.serialize_entry(
+                        &format!("{}_utc", key),
+                        &dt.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Secs, true),
+                    )?;
+                }
+                None => {
+                    s.serialize_entry(key, "")?;
+                    s.serialize_entry(&format!("{}_local", key), "")?;
+                    s.serialize_entry(&format!("{}_utc", key), "")?;
+                }
             }
-            None => s.serialize_entry("updated", "")?,
         }
         s.serialize_entry("filepath", &self.filepath)?;
         s.serialize_entry("meta", &SerYaml(&self.meta))?;
         s.serialize_entry("content", &self.content)?;
+        s.serialize_entry("toc", &self.toc)?;
+        s.serialize_entry("summary", &self.summary)?;
+        s.serialize_entry("word_count", &self.word_count.total())?;
+        s.serialize_entry("reading_time", &self.reading_time)?;
         s.end()
     }
 }
@@ -0,0 +1,99 @@
+//! This module handles sitemap and robots.txt generation.
+
+use std::fmt::Write;
+
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use yaml_rust::Yaml;
+
+use crate::{escape_xml, Entry, Instance};
+
+/// A URL listed in the sitemap.
+#[derive(Debug)]
+pub struct SitemapUrl {
+    /// URL path of the resource.
+    pub loc: String,
+    pub lastmod: Option<DateTime<FixedOffset>>,
+}
+
+impl SitemapUrl {
+    /// Create a sitemap URL for an entry whose URL is generated,
+    /// or `None` if the entry opts out by `sitemap: false` or `noindex: true`.
+    pub fn from_entry(entry: &Entry) -> Option<SitemapUrl> {
+        if entry.meta["sitemap"] == Yaml::Boolean(false)
+            || entry.meta["noindex"] == Yaml::Boolean(true)
+        {
+            return None;
+        }
+        Some(SitemapUrl {
+            loc: entry.url.clone()?,
+            lastmod: entry.updated.or(entry.created),
+        })
+    }
+}
+
+/// Render a sitemap in XML format.
+pub fn sitemap(instance: &Instance, urls: &[SitemapUrl]) -> String {
+    let mut xml = String::new();
+    xml.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    xml.push_str(r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#);
+    for url in urls {
+        write!(
+            xml,
+            "<url><loc>{}</loc>",
+            escape_xml(&instance.site.absolute_url(&url.loc))
+        )
+        .unwrap();
+        if let Some(lastmod) = &url.lastmod {
+            write!(
+                xml,
+                "<lastmod>{}</lastmod>",
+                lastmod.to_rfc3339_opts(SecondsFormat::Secs, true)
+            )
+            .unwrap();
+        }
+        xml.push_str("</url>");
+    }
+    xml.push_str("</urlset>");
+    xml
+}
+
+/// Render a robots.txt which allows everything and points to the sitemap.
+pub fn robots(instance: &Instance, sitemap_url: &str) -> String {
+    format!(
+        "User-agent: *\nAllow: /\n\nSitemap: {}\n",
+        instance.site.absolute_url(sitemap_url)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlLoader;
+
+    use super::*;
+
+    #[test]
+    fn test_sitemap() {
+        let inst = Instance::new("tests/test_inst").unwrap();
+        let mut posts = inst.load_posts(true).unwrap();
+        posts
+            .iter_mut()
+            .for_each(|p| p.url = Some("/post/foo/".to_string()));
+        posts[0].meta = YamlLoader::load_from_str("sitemap: false")
+            .unwrap()
+            .remove(0);
+        let mut urls = vec![SitemapUrl {
+            loc: "/".to_string(),
+            lastmod: None,
+        }];
+        urls.extend(posts.iter().filter_map(SitemapUrl::from_entry));
+        assert_eq!(urls.len(), 2);
+
+        let xml = sitemap(&inst, &urls);
+        assert!(xml.contains("<url><loc>https://example.com/</loc></url>"));
+        assert!(xml.contains(
+            "<url><loc>https://example.com/post/foo/</loc>\
+            <lastmod>2020-08-31T00:00:00+08:00</lastmod></url>"
+        ));
+        assert!(robots(&inst, "/sitemap.xml").contains("Sitemap: https://example.com/sitemap.xml"));
+    }
+}
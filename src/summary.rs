@@ -0,0 +1,293 @@
+//! This module handles summaries and statistics of entries.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{escape_xml, html_to_text};
+
+/// Placeholder of the `<!-- more -->` marker, which survives Markdown rendering as a paragraph.
+const MORE_PLACEHOLDER: &str = "\u{fffc}more\u{fffc}";
+
+/// Options of automatic summaries, given in the `[summary]` section of `pressure.toml`.
+/// They apply to entries without a `<!-- more -->` marker or a `summary` field in front matter.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SummaryConfig {
+    /// Number of leading paragraphs to take.
+    pub paragraphs: usize,
+    /// Max number of characters to take, where a CJK character counts as one,
+    /// and other words are not split.
+    pub max_chars: Option<usize>,
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        Self {
+            paragraphs: 1,
+            max_chars: None,
+        }
+    }
+}
+
+/// Reading speeds for estimating reading time, given in the `[reading_time]` section of
+/// `pressure.toml`, per script, since CJK text is read by characters instead of words.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReadingTimeConfig {
+    /// Words per minute for text written with spaces between words.
+    pub words_per_minute: u32,
+    /// Characters per minute for CJK text.
+    pub cjk_chars_per_minute: u32,
+}
+
+impl Default for ReadingTimeConfig {
+    fn default() -> Self {
+        Self {
+            words_per_minute: 200,
+            cjk_chars_per_minute: 400,
+        }
+    }
+}
+
+/// Numbers of words in text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WordCount {
+    /// Words written with spaces between them, e.g. English words.
+    pub words: usize,
+    /// CJK characters, each of which counts as a word.
+    pub cjk_chars: usize,
+}
+
+impl WordCount {
+    /// Count words in plain text, where CJK characters are counted individually,
+    /// and other words are separated by whitespace or CJK characters.
+    pub fn count(text: &str) -> WordCount {
+        let mut count = WordCount::default();
+        for token in text.split_whitespace() {
+            for segment in token.split(is_cjk) {
+                if segment.chars().any(char::is_alphanumeric) {
+                    count.words += 1;
+                }
+            }
+            count.cjk_chars += token
+                .chars()
+                .filter(|&c| is_cjk(c) && c.is_alphanumeric())
+                .count();
+        }
+        count
+    }
+
+    pub fn total(&self) -> usize {
+        self.words + self.cjk_chars
+    }
+
+    /// Estimated reading time in minutes, rounded up, at least one minute for non-empty text.
+    pub fn reading_time(&self, config: &ReadingTimeConfig) -> u32 {
+        let minutes = self.words as f64 / config.words_per_minute.max(1) as f64
+            + self.cjk_chars as f64 / config.cjk_chars_per_minute.max(1) as f64;
+        minutes.ceil() as u32
+    }
+}
+
+/// Whether a character is written without spaces between words, as in Chinese and Japanese.
+/// Hangul is not, since Korean separates words with spaces.
+pub(crate) fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{2e80}'..='\u{9fff}'       // radicals, punctuation, kana and unified ideographs
+        | '\u{f900}'..='\u{faff}'     // compatibility ideographs
+        | '\u{ff00}'..='\u{ffef}'     // fullwidth forms
+        | '\u{20000}'..='\u{2ffff}') // extension ideographs
+}
+
+/// Replace the first `<!-- more -->` line outside code blocks with a placeholder,
+/// which is to be found by `split_more` after rendering.
+pub(crate) fn mark_more(md: &str) -> String {
+    let mut in_code_block = false;
+    let mut marked = false;
+    let mut lines = vec![];
+    for line in md.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code_block = !in_code_block;
+        }
+        let is_marker = trimmed
+            .strip_prefix("<!--")
+            .and_then(|s| s.strip_suffix("-->"))
+            .map(str::trim)
+            == Some("more");
+        if is_marker && !in_code_block && !marked {
+            lines.push(format!("\n{}\n", MORE_PLACEHOLDER));
+            marked = true;
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    lines.join("\n")
+}
+
+/// Remove the placeholder of the `<!-- more -->` marker from rendered content,
+/// and return the content before it as the summary.
+pub(crate) fn split_more(html: String) -> (String, Option<String>) {
+    let paragraph = format!("<p>{}</p>\n", MORE_PLACEHOLDER);
+    match html.find(&paragraph) {
+        Some(pos) => {
+            let summary = html[..pos].trim_end().to_string();
+            let content = format!("{}{}", &html[..pos], &html[pos + paragraph.len()..]);
+            (content, Some(summary))
+        }
+        None => (html, None),
+    }
+}
+
+/// Elements without closing tags.
+const VOID_ELEMENTS: [&str; 6] = ["br", "hr", "img", "input", "meta", "link"];
+
+/// Take the leading paragraphs of rendered content,
+/// cut into plain text if it's longer than the max number of characters.
+/// The content is cut at the end of the top-level element of the last paragraph taken,
+/// so that tags are balanced, e.g. a leading blockquote is taken as a whole.
+pub(crate) fn auto_summary(content: &str, config: &SummaryConfig) -> String {
+    let mut end = if config.paragraphs == 0 { 0 } else { content.len() };
+    let mut paragraphs = 0;
+    let mut depth = 0usize;
+    let mut pos = 0;
+    while end > 0 {
+        let begin = match content[pos..].find('<') {
+            Some(begin) => pos + begin,
+            None => break,
+        };
+        pos = match content[begin..].find('>') {
+            Some(close) => begin + close + 1,
+            None => break,
+        };
+        let tag = &content[begin + 1..pos - 1];
+        if let Some(name) = tag.strip_prefix('/') {
+            depth = depth.saturating_sub(1);
+            if name.trim().eq_ignore_ascii_case("p") {
+                paragraphs += 1;
+            }
+            if depth == 0 && paragraphs >= config.paragraphs {
+                end = pos;
+                break;
+            }
+        } else {
+            let name = tag.split_whitespace().next().unwrap_or_default();
+            let is_void = VOID_ELEMENTS.iter().any(|v| v.eq_ignore_ascii_case(name));
+            if !tag.starts_with('!') && !tag.ends_with('/') && !is_void {
+                depth += 1;
+            }
+        }
+    }
+    let summary = &content[..end];
+    if let Some(max_chars) = config.max_chars {
+        if let Some(text) = truncate_text(&html_to_text(summary), max_chars) {
+            return format!("<p>{}</p>", escape_xml(&text));
+        }
+    }
+    summary.to_string()
+}
+
+/// Truncate text to the max number of characters, with an ellipsis appended.
+/// Words other than CJK characters are kept whole, unless a single word is too long.
+/// Return `None` if the text is short enough.
+fn truncate_text(text: &str, max_chars: usize) -> Option<String> {
+    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= max_chars {
+        return None;
+    }
+    let is_word_char = |c: char| c.is_alphanumeric() && !is_cjk(c);
+    let mut cut = max_chars;
+    // don't break a word in the middle
+    while cut > 0 && is_word_char(chars[cut - 1]) && is_word_char(chars[cut]) {
+        cut -= 1;
+    }
+    if cut == 0 {
+        cut = max_chars;
+    }
+    let truncated: String = chars[..cut].iter().collect();
+    Some(format!("{}…", truncated.trim_end()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_more() {
+        let md = "foo\n<!-- more -->\nbar\n\n```\n<!--more-->\n```";
+        let marked = mark_more(md);
+        assert_eq!(marked.matches(MORE_PLACEHOLDER).count(), 1);
+        let html = format!("<p>foo</p>\n<p>{}</p>\n<p>bar</p>\n", MORE_PLACEHOLDER);
+        let (content, summary) = split_more(html);
+        assert_eq!(content, "<p>foo</p>\n<p>bar</p>\n");
+        assert_eq!(summary.as_deref(), Some("<p>foo</p>"));
+        assert_eq!(split_more("<p>foo</p>".to_string()).1, None);
+        assert!(!mark_more("<!-->\n<!--->").contains(MORE_PLACEHOLDER));
+    }
+
+    #[test]
+    fn test_auto_summary() {
+        let content = "<p>foo</p>\n<p>bar</p>\n<p>baz</p>\n";
+        let mut config = SummaryConfig::default();
+        assert_eq!(auto_summary(content, &config), "<p>foo</p>");
+        assert_eq!(auto_summary("<h2>foo</h2>", &config), "<h2>foo</h2>");
+        config.paragraphs = 2;
+        assert_eq!(auto_summary(content, &config), "<p>foo</p>\n<p>bar</p>");
+        config.max_chars = Some(10);
+        assert_eq!(auto_summary(content, &config), "<p>foo</p>\n<p>bar</p>");
+        config.max_chars = Some(6);
+        assert_eq!(auto_summary(content, &config), "<p>foo…</p>");
+        config.max_chars = Some(3);
+        assert_eq!(
+            auto_summary("<p>a &lt;b&gt;</p>", &config),
+            "<p>a &lt;…</p>"
+        );
+
+        let config = SummaryConfig::default();
+        let quote = "<blockquote>\n<p>foo<br />\nbar</p>\n<p>baz</p>\n</blockquote>";
+        let content = format!("{}\n<p>qux</p>\n", quote);
+        assert_eq!(auto_summary(&content, &config), quote);
+        let list = "<ul>\n<li>\n<p>foo</p>\n</li>\n<li>\n<p>bar</p>\n</li>\n</ul>";
+        let content = format!("{}\n<p>baz</p>\n", list);
+        assert_eq!(auto_summary(&content, &config), list);
+    }
+
+    #[test]
+    fn test_word_count() {
+        let count = WordCount::count("用 Rust 写博客，don't panic! C++ —");
+        assert_eq!(
+            count,
+            WordCount {
+                words: 4,
+                cjk_chars: 4
+            }
+        );
+        assert_eq!(count.total(), 8);
+        assert_eq!(WordCount::count("喵喵喵abc喵").words, 1);
+        assert_eq!(
+            WordCount::count("안녕하세요 세계"),
+            WordCount {
+                words: 2,
+                cjk_chars: 0
+            }
+        );
+
+        let config = ReadingTimeConfig::default();
+        assert_eq!(WordCount::default().reading_time(&config), 0);
+        assert_eq!(count.reading_time(&config), 1);
+        let count = WordCount {
+            words: 300,
+            cjk_chars: 200,
+        };
+        assert_eq!(count.reading_time(&config), 2);
+    }
+
+    #[test]
+    fn test_truncate_text() {
+        assert_eq!(truncate_text("hello world", 11), None);
+        assert_eq!(truncate_text("hello world", 8).unwrap(), "hello…");
+        assert_eq!(truncate_text("helloworld", 5).unwrap(), "hello…");
+        assert_eq!(truncate_text("这是一个测试文章", 4).unwrap(), "这是一个…");
+        assert_eq!(truncate_text("用 Rust 写博客", 4).unwrap(), "用…");
+    }
+}
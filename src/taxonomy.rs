@@ -0,0 +1,636 @@
+//! This module handles taxonomies, by which posts are classified, e.g. categories and tags.
+
+use std::{borrow::Borrow, collections::BTreeMap};
+
+use deunicode::deunicode;
+use serde::{Deserialize, Serialize};
+use yaml_rust::Yaml;
+
+use crate::{slugify, Entry, PressError, PressResult};
+
+/// Path segments taken by other routes, which taxonomies can't use.
+pub(crate) const RESERVED_PATHS: [&str; 6] = ["post", "page", "archive", "search", "static", "theme"];
+
+/// Names of other routes, which routes of taxonomies can't take, or `url_for` would be confused.
+const RESERVED_ROUTES: [&str; 20] = [
+    "index",
+    "index_page",
+    "post",
+    "archive",
+    "archive_page",
+    "archive_year",
+    "archive_year_page",
+    "archive_month",
+    "archive_month_page",
+    "atom",
+    "rss",
+    "json_feed",
+    "sitemap_xml",
+    "search",
+    "search_index_json",
+    "robots_txt",
+    "live_reload_events",
+    "static",
+    "theme.static",
+    "page",
+];
+
+/// A taxonomy, given as `[[taxonomies]]` in `pressure.toml`, besides the built-in categories
+/// and tags. Its terms are listed in a field of front matter of posts, either as a string or
+/// as an array of strings.
+#[derive(Debug, Serialize, Clone)]
+pub struct Taxonomy {
+    /// Field of front matter holding the terms, e.g. `tags`,
+    /// which is also the path of the list of all terms, e.g. `/tags/`.
+    pub name: String,
+    /// Path of posts of a term, e.g. `tag` for `/tag/<term>/`, the same as the name if not given.
+    pub singular: Option<String>,
+    /// Title shown in templates, e.g. `Tag`, the capitalized singular if not given.
+    pub title: Option<String>,
+    /// Whether to generate Atom feeds of terms, if Atom feeds are enabled.
+    pub feed: bool,
+    /// Whether terms are paths like `Dev/Rust/Async`, where a term includes its descendants.
+    pub hierarchical: bool,
+    /// Options of slugs, given in the `[slugs]` section of `pressure.toml` for all taxonomies.
+    #[serde(skip)]
+    pub slugs: SlugConfig,
+}
+
+impl Default for Taxonomy {
+    fn default() -> Self {
+        Self {
+            name: "".to_string(),
+            singular: None,
+            title: None,
+            feed: true,
+            hierarchical: false,
+            slugs: SlugConfig::default(),
+        }
+    }
+}
+
+/// A taxonomy as given in `pressure.toml`, where options left out are taken from the built-in
+/// taxonomy of the same name, if any, e.g. `hierarchical` of `categories`.
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TaxonomyConfig {
+    pub name: String,
+    pub singular: Option<String>,
+    pub title: Option<String>,
+    pub feed: Option<bool>,
+    pub hierarchical: Option<bool>,
+}
+
+impl TaxonomyConfig {
+    /// Override options of the given taxonomy with the ones given here.
+    fn apply_to(self, taxonomy: Taxonomy) -> Taxonomy {
+        Taxonomy {
+            name: self.name,
+            singular: self.singular.or(taxonomy.singular),
+            title: self.title.or(taxonomy.title),
+            feed: self.feed.unwrap_or(taxonomy.feed),
+            hierarchical: self.hierarchical.unwrap_or(taxonomy.hierarchical),
+            slugs: taxonomy.slugs,
+        }
+    }
+}
+
+/// Options of slugs of terms, which make URLs of terms, given in the `[slugs]` section of
+/// `pressure.toml`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SlugConfig {
+    /// Whether to transliterate text into ASCII, e.g. `奇技淫巧` -> `qi-ji-yin-qiao`,
+    /// otherwise letters and digits of any language are kept.
+    pub transliterate: bool,
+    /// Words to replace symbols with, since other symbols are dropped, e.g. `C++` -> `c-plus-plus`.
+    pub replacements: BTreeMap<String, String>,
+}
+
+impl Default for SlugConfig {
+    fn default() -> Self {
+        Self {
+            transliterate: false,
+            replacements: btreemap! {
+                "+".to_string() => "plus".to_string(),
+                "#".to_string() => "sharp".to_string(),
+                "&".to_string() => "and".to_string(),
+            },
+        }
+    }
+}
+
+impl SlugConfig {
+    /// Make a lowercase slug of text, or a slug like `term-1a2b3c4d` by the hash of the text
+    /// if nothing is left, e.g. for `???`, so that slugs are always safe in URLs and paths.
+    pub fn slugify(&self, text: &str) -> String {
+        let mut replaced = text.to_string();
+        for (symbol, word) in &self.replacements {
+            replaced = replaced.replace(symbol.as_str(), &format!(" {} ", word));
+        }
+        if self.transliterate {
+            replaced = deunicode(&replaced);
+        }
+        let slug = slugify(&replaced);
+        if slug.is_empty() {
+            format!("term-{:08x}", fnv1a(&text.trim().to_lowercase()))
+        } else {
+            slug
+        }
+    }
+}
+
+/// 32-bit FNV-1a hash, which unlike the hasher of the standard library is stable across
+/// Rust versions, so that URLs don't change.
+fn fnv1a(text: &str) -> u32 {
+    text.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Number of posts of a term, whose name is the first one seen among terms of the same slug.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TermCount {
+    pub name: String,
+    pub count: usize,
+}
+
+impl Taxonomy {
+    fn new(name: &str, singular: &str) -> Taxonomy {
+        Taxonomy {
+            name: name.to_string(),
+            singular: Some(singular.to_string()),
+            ..Taxonomy::default()
+        }
+    }
+
+    pub fn singular(&self) -> &str {
+        self.singular.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn title(&self) -> String {
+        match &self.title {
+            Some(title) => title.clone(),
+            None => {
+                let mut chars = self.singular().chars();
+                chars
+                    .next()
+                    .map(|c| c.to_uppercase().chain(chars).collect())
+                    .unwrap_or_default()
+            }
+        }
+    }
+
+    /// Name of the route of the list of all terms, e.g. `tags_list`.
+    pub fn list_route(&self) -> String {
+        format!("{}_list", self.name)
+    }
+
+    /// Name of the route of posts of a term, e.g. `tag`,
+    /// which is suffixed with `_page` for other pages and `_atom` for the feed.
+    pub fn term_route(&self) -> &str {
+        self.singular()
+    }
+
+    /// Names of all routes of the taxonomy.
+    fn route_names(&self) -> Vec<String> {
+        let route = self.term_route();
+        vec![
+            self.list_route(),
+            route.to_string(),
+            format!("{}_page", route),
+            format!("{}_atom", route),
+        ]
+    }
+
+    /// Terms of the taxonomy in front matter of an entry.
+    pub fn terms<'a>(&self, meta: &'a Yaml) -> impl Iterator<Item = &'a str> {
+        meta[self.name.as_str()]
+            .as_vec()
+            .into_iter()
+            .flatten()
+            .filter_map(|term| term.as_str())
+    }
+
+    /// A term and its ancestors if the taxonomy is hierarchical, from the root down,
+    /// e.g. `Dev`, `Dev/Rust` and `Dev/Rust/Async` for `Dev/Rust/Async`.
+    pub fn lineage<'a>(&self, term: &'a str) -> Vec<&'a str> {
+        if !self.hierarchical {
+            return vec![term];
+        }
+        term.match_indices('/')
+            .map(|(i, _)| &term[..i])
+            .chain(std::iter::once(term))
+            .collect()
+    }
+
+    /// Slug of a term, made of slugs of segments if hierarchical, e.g. `Dev/C++` ->
+    /// `dev/c-plus-plus`. Slugs are slugs of themselves.
+    pub fn slug(&self, term: &str) -> String {
+        if !self.hierarchical {
+            return self.slugs.slugify(term);
+        }
+        term.split('/')
+            .map(|segment| self.slugs.slugify(segment))
+            .collect::<Vec<String>>()
+            .join("/")
+    }
+
+    /// Count posts of each term, keyed by slugs, where terms of the same slug are taken as one,
+    /// and a post counts for ancestors of its terms as well if the taxonomy is hierarchical.
+    pub fn count_terms<E: Borrow<Entry>>(&self, posts: &[E]) -> BTreeMap<String, TermCount> {
+        let mut counts: BTreeMap<String, TermCount> = BTreeMap::new();
+        for entry in posts {
+            let terms: BTreeMap<String, &str> = self
+                .terms(&entry.borrow().meta)
+                .flat_map(|term| self.lineage(term))
+                .map(|term| (self.slug(term), term))
+                .collect();
+            for (slug, name) in terms {
+                counts
+                    .entry(slug)
+                    .or_insert_with(|| TermCount {
+                        name: name.to_string(),
+                        count: 0,
+                    })
+                    .count += 1;
+            }
+        }
+        counts
+    }
+
+    /// Keep posts of a term, given by the term itself or its slug, including posts of its
+    /// descendants if the taxonomy is hierarchical.
+    pub fn filter_posts<E: Borrow<Entry>>(&self, posts: Vec<E>, term: &str) -> Vec<E> {
+        let slug = self.slug(term);
+        posts
+            .into_iter()
+            .filter(|p| {
+                self.terms(&p.borrow().meta)
+                    .any(|entry_term| self.includes(&slug, &self.slug(entry_term)))
+            })
+            .collect()
+    }
+
+    /// Whether a term given to an entry falls under the given term,
+    /// being the same term or one of its descendants.
+    pub fn includes(&self, term: &str, entry_term: &str) -> bool {
+        entry_term == term
+            || (self.hierarchical
+                && entry_term.starts_with(term)
+                && entry_term[term.len()..].starts_with('/'))
+    }
+
+    /// Make a term canonical, by trimming whitespace and empty segments of paths if hierarchical,
+    /// e.g. ` Dev / Rust/` -> `Dev/Rust`.
+    fn canonicalize_term(&self, term: &str) -> String {
+        if !self.hierarchical {
+            return term.to_string();
+        }
+        term.split('/')
+            .map(|segment| segment.trim())
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<&str>>()
+            .join("/")
+    }
+}
+
+/// A term in the tree of a hierarchical taxonomy, or a term of a flat one which has no children.
+#[derive(Debug, Clone, Serialize)]
+pub struct TermNode {
+    /// Last segment of the path, e.g. `Async`.
+    pub name: String,
+    /// The whole term, e.g. `Dev/Rust/Async`.
+    pub path: String,
+    pub slug: String,
+    /// Number of posts of the term and its descendants.
+    pub count: usize,
+    pub url: String,
+    pub children: Vec<TermNode>,
+}
+
+impl TermNode {
+    fn new(slug: &str, term: Option<&TermCount>, url: String) -> TermNode {
+        let path = term.map_or(slug, |term| term.name.as_str());
+        TermNode {
+            name: path.rsplit('/').next().unwrap_or_default().to_string(),
+            path: path.to_string(),
+            slug: slug.to_string(),
+            count: term.map_or(0, |term| term.count),
+            url,
+            children: vec![],
+        }
+    }
+}
+
+/// Build the tree of terms from post counts of all terms keyed by slugs,
+/// where ancestors of a term always come along, as given by `Taxonomy::count_terms`.
+/// `term_url` makes URLs of terms from slugs.
+pub(crate) fn term_tree<F>(counts: &BTreeMap<String, TermCount>, term_url: F) -> Vec<TermNode>
+where
+    F: Fn(&str) -> String,
+{
+    fn insert(nodes: &mut Vec<TermNode>, node: TermNode) {
+        match nodes
+            .iter_mut()
+            .find(|n| node.slug.starts_with(&format!("{}/", n.slug)))
+        {
+            Some(parent) => insert(&mut parent.children, node),
+            None => nodes.push(node),
+        }
+    }
+    // sort by segments, so that a parent sorts before its children, and siblings by slug
+    let mut slugs: Vec<&String> = counts.keys().collect();
+    slugs.sort_by(|s1, s2| s1.split('/').cmp(s2.split('/')));
+    let mut roots = vec![];
+    for slug in slugs {
+        insert(&mut roots, TermNode::new(slug, counts.get(slug), term_url(slug)));
+    }
+    roots
+}
+
+/// Find a term in the tree of terms by its slug.
+pub(crate) fn find_term<'a>(nodes: &'a [TermNode], slug: &str) -> Option<&'a TermNode> {
+    for node in nodes {
+        if node.slug == slug {
+            return Some(node);
+        }
+        if slug.starts_with(&format!("{}/", node.slug)) {
+            return find_term(&node.children, slug);
+        }
+    }
+    None
+}
+
+/// Nodes from the root down to the term of the given slug, e.g. `Dev`, `Dev/Rust` and
+/// `Dev/Rust/Async`, without children.
+pub(crate) fn term_breadcrumbs<F>(
+    taxonomy: &Taxonomy,
+    slug: &str,
+    counts: &BTreeMap<String, TermCount>,
+    term_url: F,
+) -> Vec<TermNode>
+where
+    F: Fn(&str) -> String,
+{
+    taxonomy
+        .lineage(slug)
+        .into_iter()
+        .map(|slug| TermNode::new(slug, counts.get(slug), term_url(slug)))
+        .collect()
+}
+
+/// Built-in taxonomies, hierarchical categories and flat tags.
+pub fn default_taxonomies() -> Vec<Taxonomy> {
+    vec![
+        Taxonomy {
+            hierarchical: true,
+            ..Taxonomy::new("categories", "category")
+        },
+        Taxonomy::new("tags", "tag"),
+    ]
+}
+
+/// Add taxonomies given in `pressure.toml` to the built-in ones, where the ones of the same
+/// names as the built-in ones change their options, and check that they don't conflict.
+pub(crate) fn merge_taxonomies(
+    configured: Vec<TaxonomyConfig>,
+    slugs: &SlugConfig,
+) -> PressResult<Vec<Taxonomy>> {
+    let mut taxonomies = default_taxonomies();
+    for config in configured {
+        match taxonomies.iter_mut().find(|t| t.name == config.name) {
+            Some(existing) => *existing = config.apply_to(existing.clone()),
+            None => taxonomies.push(config.apply_to(Taxonomy::default())),
+        }
+    }
+    for taxonomy in taxonomies.iter_mut() {
+        taxonomy.slugs = slugs.clone();
+    }
+
+    let bad_taxonomy = |name: &str, reason: &str| {
+        Err(PressError::new(&format!(
+            "Bad taxonomy `{}`: {}",
+            name, reason
+        )))
+    };
+    let is_path = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    for (i, taxonomy) in taxonomies.iter().enumerate() {
+        for path in vec![taxonomy.name.as_str(), taxonomy.singular()] {
+            if !is_path(path) {
+                return bad_taxonomy(
+                    &taxonomy.name,
+                    "`name` and `singular` should consist of ASCII letters, digits, `-` and `_`",
+                );
+            }
+            if RESERVED_PATHS.contains(&path) {
+                return bad_taxonomy(&taxonomy.name, &format!("`{}` is reserved", path));
+            }
+        }
+        for route in taxonomy.route_names() {
+            if RESERVED_ROUTES.contains(&route.as_str()) {
+                return bad_taxonomy(&taxonomy.name, &format!("route `{}` is reserved", route));
+            }
+        }
+        for other in &taxonomies[..i] {
+            let other_routes = other.route_names();
+            if let Some(route) = taxonomy
+                .route_names()
+                .into_iter()
+                .find(|route| other_routes.contains(route))
+            {
+                return bad_taxonomy(
+                    &taxonomy.name,
+                    &format!("route `{}` is taken by `{}`", route, other.name),
+                );
+            }
+            if other.name == taxonomy.singular() || other.singular() == taxonomy.name {
+                return bad_taxonomy(&taxonomy.name, &format!("conflicts with `{}`", other.name));
+            }
+            if other.singular() == taxonomy.singular() {
+                return bad_taxonomy(&taxonomy.name, "`singular` is used twice");
+            }
+        }
+    }
+    Ok(taxonomies)
+}
+
+/// Make a field of front matter an array of canonical terms, if it's a single term or missing.
+pub(crate) fn normalize_terms(meta: &mut Yaml, taxonomy: &Taxonomy) {
+    let key = taxonomy.name.as_str();
+    if let Some(val) = meta.get_mut(key) {
+        if let Yaml::String(_) = val {
+            *val = Yaml::Array(vec![val.clone()])
+        }
+        if let Yaml::Array(terms) = val {
+            for term in terms.iter_mut() {
+                if let Yaml::String(s) = term {
+                    *s = taxonomy.canonicalize_term(s);
+                }
+            }
+        }
+    } else {
+        meta.as_hash_mut()
+            .unwrap()
+            .insert(Yaml::String(key.to_owned()), Yaml::Array(vec![]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlLoader;
+
+    use super::*;
+
+    #[test]
+    fn test_merge_taxonomies() {
+        #[derive(Deserialize)]
+        struct Info {
+            taxonomies: Vec<TaxonomyConfig>,
+        }
+        let info: Info = toml::from_str(
+            "[[taxonomies]]\nname = \"series\"\n\n\
+             [[taxonomies]]\nname = \"tags\"\nsingular = \"label\"\nfeed = false\n\n\
+             [[taxonomies]]\nname = \"categories\"\ntitle = \"Topic\"",
+        )
+        .unwrap();
+        let taxonomies = merge_taxonomies(info.taxonomies, &SlugConfig::default()).unwrap();
+        let names: Vec<&str> = taxonomies.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["categories", "tags", "series"]);
+        assert_eq!(taxonomies[0].title(), "Topic");
+        assert_eq!(taxonomies[0].term_route(), "category");
+        assert!(taxonomies[0].hierarchical);
+        assert_eq!(taxonomies[1].term_route(), "label");
+        assert!(!taxonomies[1].feed);
+        assert_eq!(taxonomies[2].singular(), "series");
+        assert_eq!(taxonomies[2].list_route(), "series_list");
+
+        for name in &["archive", "foo bar", "category", ""] {
+            let config = TaxonomyConfig {
+                name: name.to_string(),
+                ..TaxonomyConfig::default()
+            };
+            assert!(merge_taxonomies(vec![config], &SlugConfig::default()).is_err());
+        }
+        for (name, singular) in &[
+            ("indexes", "index"),
+            ("atoms", "atom"),
+            ("rsses", "rss"),
+            ("jsons", "json_feed"),
+            ("archives", "archive_year"),
+            ("tag_pages", "tag_page"),
+            ("categories_lists", "categories_list"),
+        ] {
+            let config = TaxonomyConfig {
+                name: name.to_string(),
+                singular: Some(singular.to_string()),
+                ..TaxonomyConfig::default()
+            };
+            assert!(merge_taxonomies(vec![config], &SlugConfig::default()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_terms() {
+        let mut meta = YamlLoader::load_from_str("series: Rust\ntags: [a, b]")
+            .unwrap()
+            .remove(0);
+        let series = Taxonomy::new("series", "series");
+        let tags = Taxonomy::new("tags", "tag");
+        let authors = Taxonomy::new("authors", "author");
+        for taxonomy in &[&series, &tags, &authors] {
+            normalize_terms(&mut meta, taxonomy);
+        }
+        assert_eq!(series.terms(&meta).collect::<Vec<&str>>(), vec!["Rust"]);
+        assert_eq!(tags.terms(&meta).collect::<Vec<&str>>(), vec!["a", "b"]);
+        assert_eq!(meta["authors"], Yaml::Array(vec![]));
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        let categories = &default_taxonomies()[0];
+        let mut meta = YamlLoader::load_from_str("categories: ' Dev / Rust/Async/'")
+            .unwrap()
+            .remove(0);
+        normalize_terms(&mut meta, categories);
+        assert_eq!(meta["categories"][0].as_str(), Some("Dev/Rust/Async"));
+        assert_eq!(
+            categories.lineage("Dev/Rust/Async"),
+            vec!["Dev", "Dev/Rust", "Dev/Rust/Async"]
+        );
+        assert!(categories.includes("Dev", "Dev/Rust"));
+        assert!(!categories.includes("Dev", "Devops"));
+        let tags = &default_taxonomies()[1];
+        assert_eq!(tags.lineage("a/b"), vec!["a/b"]);
+        assert!(!tags.includes("a", "a/b"));
+
+        let posts: Vec<Entry> = vec![
+            "Dev/Rust/Async",
+            "Dev/Go",
+            "dev/rust",
+            "Dev-Ops",
+            "Life",
+        ]
+        .into_iter()
+        .map(|term| {
+            let mut meta = YamlLoader::load_from_str(&format!("categories: {}", term))
+                .unwrap()
+                .remove(0);
+            normalize_terms(&mut meta, categories);
+            Entry {
+                meta,
+                ..Entry::default()
+            }
+        })
+        .collect();
+        let counts = categories.count_terms(&posts);
+        assert_eq!(counts["dev"].count, 3);
+        assert_eq!(counts["dev/rust"].name, "Dev/Rust");
+        assert_eq!(categories.filter_posts(posts.clone(), "DEV/Rust").len(), 2);
+        assert_eq!(categories.filter_posts(posts, "dev").len(), 3);
+
+        let tree = term_tree(&counts, |slug| format!("/category/{}/", slug));
+        let names: Vec<&str> = tree.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, vec!["Dev", "Dev-Ops", "Life"]);
+        let dev = &tree[0];
+        assert_eq!(dev.count, 3);
+        let children: Vec<&str> = dev.children.iter().map(|n| n.path.as_str()).collect();
+        assert_eq!(children, vec!["Dev/Go", "Dev/Rust"]);
+        assert_eq!(dev.children[1].children[0].name, "Async");
+        assert_eq!(dev.children[1].children[0].url, "/category/dev/rust/async/");
+        assert_eq!(find_term(&tree, "dev/rust/async").unwrap().count, 1);
+        assert!(find_term(&tree, "dev/java").is_none());
+
+        let breadcrumbs = term_breadcrumbs(categories, "dev/rust", &counts, |s| s.to_string());
+        let paths: Vec<&str> = breadcrumbs.iter().map(|n| n.path.as_str()).collect();
+        assert_eq!(paths, vec!["Dev", "Dev/Rust"]);
+        assert_eq!(breadcrumbs[1].name, "Rust");
+    }
+
+    #[test]
+    fn test_slug() {
+        let mut config = SlugConfig::default();
+        assert_eq!(config.slugify("C++"), "c-plus-plus");
+        assert_eq!(config.slugify("C#"), "c-sharp");
+        assert_eq!(config.slugify("Hello, World"), "hello-world");
+        assert_eq!(config.slugify("奇技淫巧"), "奇技淫巧");
+        assert_eq!(config.slugify("???"), format!("term-{:08x}", fnv1a("???")));
+        assert_ne!(config.slugify("???"), config.slugify("!!!"));
+        assert!(config.slugify("..").starts_with("term-"));
+        assert_eq!(fnv1a("a"), 0xe40c_292c);
+        config.transliterate = true;
+        assert_eq!(config.slugify("奇技淫巧"), "qi-ji-yin-qiao");
+        assert_eq!(config.slugify("Café"), "cafe");
+
+        let categories = Taxonomy {
+            slugs: config,
+            ..default_taxonomies().remove(0)
+        };
+        assert_eq!(categories.slug("Dev/C++"), "dev/c-plus-plus");
+        assert_eq!(categories.slug("dev/c-plus-plus"), "dev/c-plus-plus");
+    }
+}
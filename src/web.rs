@@ -1,89 +1,147 @@
 //! This module handles web routing and template rendering.
 
-use std::{cmp::min, collections::HashMap, lazy::OnceCell, path::PathBuf};
+use std::{
+    borrow::Borrow,
+    cmp::min,
+    collections::{BTreeMap, HashMap},
+    lazy::OnceCell,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
 
 use actix_service::Service;
 use actix_web::{
-    dev::ResourceMap, get, middleware::Logger, test::TestRequest, web, App, HttpRequest,
-    HttpResponse, HttpServer, Responder,
+    dev::{ResourceMap, ServiceRequest},
+    get,
+    middleware::Logger,
+    test::TestRequest,
+    web, App, HttpRequest, HttpResponse, HttpServer, Responder,
 };
+use chrono::Datelike;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
 use tera::{Context, Tera};
-use yaml_rust::Yaml;
 
-use crate::{Entry, EntryType, Instance, PressResult};
+use crate::{
+    find_term, inject_script, robots, sitemap, term_breadcrumbs, term_tree, watch_content,
+    watch_site, ContentIndex, Entry, EntryType, Feed, FeedFormat, Instance, LiveReload, PressError,
+    PressResult, PublishedKey, SearchDocument, SearchIndex, SharedIndex, SitemapUrl, Taxonomy,
+    TermCount, TermNode,
+};
 
 fn new_context(state: &web::Data<State>) -> Context {
     let mut ctx = Context::new();
     ctx.insert("site", &state.instance.site);
     // ctx.insert("config", &state.instance.config);
+    let term_data = state.term_data();
+    let taxonomy_trees: BTreeMap<&str, &Vec<TermNode>> = state
+        .instance
+        .taxonomies
+        .iter()
+        .filter(|taxonomy| taxonomy.hierarchical)
+        .map(|taxonomy| (taxonomy.name.as_str(), &term_data.trees[&taxonomy.name]))
+        .collect();
+    ctx.insert("taxonomies", &term_data.totals);
+    ctx.insert("taxonomy_trees", &taxonomy_trees);
     ctx
 }
 
-impl Entry {
-    fn generate_url(&mut self, req: &HttpRequest) {
-        match self.etype {
-            EntryType::Post => {
-                let elems: Vec<&str> = self
-                    .filepath
-                    .file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .splitn(4, "-")
-                    .collect();
-                self.url = Some(req.url_for("post", elems).unwrap().path().to_string())
-            }
-            _ => {}
+pub(crate) fn load_posts(instance: &Instance, meta_only: bool) -> Vec<Entry> {
+    instance.load_posts(meta_only).unwrap()
+}
+
+/// Pager of a paginated listing, given to templates as `pager`.
+#[derive(Debug, Serialize)]
+struct Pager {
+    /// URL of the previous page, empty on the first page.
+    prev_url: String,
+    /// URL of the next page, empty on the last page.
+    next_url: String,
+    /// Number of the current page, starting from 1.
+    page_num: usize,
+    page_count: usize,
+    /// All pages of the listing, for rendering page links.
+    pages: Vec<PagerPage>,
+}
+
+#[derive(Debug, Serialize)]
+struct PagerPage {
+    num: usize,
+    url: String,
+    current: bool,
+}
+
+impl Pager {
+    /// Create a pager, given a function which generates the URL of a page by its number.
+    fn new<F: Fn(usize) -> String>(page_num: usize, page_count: usize, page_url: F) -> Pager {
+        Pager {
+            prev_url: if page_num > 1 {
+                page_url(page_num - 1)
+            } else {
+                "".to_string()
+            },
+            next_url: if page_num < page_count {
+                page_url(page_num + 1)
+            } else {
+                "".to_string()
+            },
+            page_num,
+            page_count,
+            pages: (1..=page_count)
+                .map(|num| PagerPage {
+                    num,
+                    url: page_url(num),
+                    current: num == page_num,
+                })
+                .collect(),
         }
     }
 }
 
-fn load_posts(instance: &Instance, req: &HttpRequest, meta_only: bool) -> Vec<Entry> {
-    let mut posts = instance.load_posts(meta_only).unwrap();
-    posts.iter_mut().for_each(|p| p.generate_url(req));
-    return posts;
+/// Take the items on the given page, or `None` if the page doesn't exist.
+fn paginate<T>(
+    items: &mut [T],
+    page_num: usize,
+    page_count: usize,
+    per_page: usize,
+) -> Option<&mut [T]> {
+    if page_num < 1 || page_num > page_count {
+        return None;
+    }
+    let begin = min(items.len(), (page_num - 1) * per_page);
+    let end = min(items.len(), begin + per_page);
+    Some(&mut items[begin..end])
 }
 
 fn handle_index_page(state: web::Data<State>, req: HttpRequest, page_num: usize) -> impl Responder {
-    let posts_per_page = state.instance.config.posts_per_index_page as usize;
-    let mut posts = load_posts(&state.instance, &req, true);
-    let post_count = posts.len();
-    let page_count = (post_count + posts_per_page - 1) / posts_per_page;
-    if page_num < 1 || page_num > page_count {
-        return HttpResponse::NotFound().finish();
-    }
-    let prev_url = if page_num == 1 {
-        "".to_string()
-    } else if page_num == 2 {
-        req.url_for_static("index").unwrap().path().to_string()
-    } else {
-        req.url_for("index_page", &[(page_num - 1).to_string()])
-            .unwrap()
-            .path()
-            .to_string()
+    let config = &state.instance.config;
+    let mut posts = state.posts();
+    let page_count = config.index_page_count(posts.len());
+    let posts_per_page = config.posts_per_index_page as usize;
+    let posts_to_render: Vec<Entry> = {
+        let posts = match paginate(&mut posts, page_num, page_count, posts_per_page) {
+            Some(posts) => posts,
+            None => return HttpResponse::NotFound().finish(),
+        };
+        let content_index = state.index.read().unwrap();
+        posts.iter().map(|p| content_index.with_content(p)).collect()
     };
-    let next_url = if page_num < page_count {
-        req.url_for("index_page", &[(page_num + 1).to_string()])
-            .unwrap()
-            .path()
-            .to_string()
-    } else {
-        "".to_string()
-    };
-    let begin = (page_num - 1) * posts_per_page;
-    let end = min(post_count, begin + posts_per_page);
-    let posts_to_render = &mut posts[begin..end];
-    posts_to_render.iter_mut().for_each(|p| {
-        p.load_content();
+    let pager = Pager::new(page_num, page_count, |page_num| {
+        let url = if page_num == 1 {
+            req.url_for_static("index")
+        } else {
+            req.url_for("index_page", &[page_num.to_string()])
+        };
+        url.unwrap().path().to_string()
     });
 
     let mut context = new_context(&state);
-    context.insert("entries", posts_to_render);
-    context.insert(
-        "pager",
-        &hashmap! {"prev_url" => prev_url, "next_url" => next_url},
-    );
-    HttpResponse::Ok().body(state.templates.render("index.html", &context).unwrap())
+    context.insert("entries", &posts_to_render);
+    context.insert("pager", &pager);
+    state.render("index.html", &context)
 }
 
 #[get("/")]
@@ -100,76 +158,644 @@ async fn index_page(
     handle_index_page(state, req, page_num)
 }
 
-#[get(r#"/post/{year:\d{4}}/{month:\d{2}}/{day:\d{2}}/{name}/"#)]
-async fn post(
+/// Render a page of an archive, category or tag listing.
+/// `route` is the name of the resource of the first page, given `elements`,
+/// while the other pages are at `<route>_page`, given `elements` and the page number.
+fn handle_archive_page(
+    state: &web::Data<State>,
+    posts: Vec<Arc<Entry>>,
+    archive_type: &str,
+    archive_name: &str,
+    page_num: usize,
+    route: &str,
+    elements: &[&str],
+) -> HttpResponse {
+    let context = archive_context(
+        state,
+        posts,
+        archive_type,
+        archive_name,
+        page_num,
+        route,
+        elements,
+    );
+    match context {
+        Some(context) => state.render("archive.html", &context),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Make the context of a page of a listing, or `None` if the page number is out of range.
+fn archive_context(
+    state: &web::Data<State>,
+    mut posts: Vec<Arc<Entry>>,
+    archive_type: &str,
+    archive_name: &str,
+    page_num: usize,
+    route: &str,
+    elements: &[&str],
+) -> Option<Context> {
+    let config = &state.instance.config;
+    let page_count = config.archive_page_count(posts.len());
+    let posts_per_page = config
+        .posts_per_archive_page
+        .map_or(posts.len(), |n| n as usize);
+    let post_counts = count_archive_periods(&posts);
+    let posts_to_render = paginate(&mut posts, page_num, page_count, posts_per_page)?;
+    let pager = Pager::new(page_num, page_count, |page_num| {
+        if page_num == 1 {
+            url_for(route, elements).unwrap()
+        } else {
+            let page_num = page_num.to_string();
+            let elements = elements.iter().copied().chain(vec![page_num.as_str()]);
+            url_for(&format!("{}_page", route), elements).unwrap()
+        }
+    });
+
+    let mut context = new_context(state);
+    context.insert(
+        "archive_years",
+        &group_by_month(posts_to_render, &post_counts),
+    );
+    let entries: Vec<&Entry> = posts_to_render.iter().map(|p| p.as_ref()).collect();
+    context.insert("entries", &entries);
+    context.insert(
+        "archive",
+        &hashmap! {"type" => archive_type, "name" => archive_name},
+    );
+    context.insert("pager", &pager);
+    Some(context)
+}
+
+/// Posts created in a year, given to templates in `archive_years`,
+/// where `count` is the number of posts of the year in the whole archive.
+#[derive(Debug, Serialize)]
+struct ArchiveYear<'a> {
+    year: i32,
+    url: String,
+    count: usize,
+    months: Vec<ArchiveMonth<'a>>,
+}
+
+/// Posts created in a month, given to templates in `archive_years`,
+/// where `count` is the number of posts of the month in the whole archive.
+#[derive(Debug, Serialize)]
+struct ArchiveMonth<'a> {
+    month: u32,
+    url: String,
+    count: usize,
+    entries: Vec<&'a Entry>,
+}
+
+/// Group posts by the year and the month they are created in, keeping their order.
+fn group_by_month<'a, E: Borrow<Entry>>(
+    posts: &'a [E],
+    post_counts: &BTreeMap<(i32, Option<u32>), usize>,
+) -> Vec<ArchiveYear<'a>> {
+    let mut years: Vec<ArchiveYear> = vec![];
+    for entry in posts {
+        let entry = entry.borrow();
+        let created = match entry.created {
+            Some(created) => created,
+            None => continue,
+        };
+        let (year, month) = (created.year(), created.month());
+        if years.last().map(|y| y.year) != Some(year) {
+            years.push(ArchiveYear {
+                year,
+                url: url_for("archive_year", &archive_date_elements(year, None)).unwrap(),
+                count: post_counts[&(year, None)],
+                months: vec![],
+            });
+        }
+        let last_year = years.last_mut().unwrap();
+        if last_year.months.last().map(|m| m.month) != Some(month) {
+            last_year.months.push(ArchiveMonth {
+                month,
+                url: url_for("archive_month", &archive_date_elements(year, Some(month))).unwrap(),
+                count: post_counts[&(year, Some(month))],
+                entries: vec![],
+            });
+        }
+        last_year.months.last_mut().unwrap().entries.push(entry);
+    }
+    years
+}
+
+/// Count posts created in each year, keyed by `(year, None)`,
+/// and in each month, keyed by `(year, Some(month))`.
+pub(crate) fn count_archive_periods<E: Borrow<Entry>>(
+    posts: &[E],
+) -> BTreeMap<(i32, Option<u32>), usize> {
+    let mut counts = BTreeMap::new();
+    for created in posts.iter().filter_map(|p| p.borrow().created) {
+        *counts.entry((created.year(), None)).or_insert(0) += 1;
+        *counts
+            .entry((created.year(), Some(created.month())))
+            .or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Keep posts created in the given year, and in the given month if any.
+pub(crate) fn filter_posts_by_date<E: Borrow<Entry>>(
+    posts: Vec<E>,
+    year: i32,
+    month: Option<u32>,
+) -> Vec<E> {
+    posts
+        .into_iter()
+        .filter(|p| match p.borrow().created {
+            Some(created) => {
+                created.year() == year && (month.is_none() || month == Some(created.month()))
+            }
+            None => false,
+        })
+        .collect()
+}
+
+/// Elements of the URL of a year or a month archive, e.g. ["2020", "08"].
+pub(crate) fn archive_date_elements(year: i32, month: Option<u32>) -> Vec<String> {
+    let mut elements = vec![format!("{:04}", year)];
+    elements.extend(month.map(|month| format!("{:02}", month)));
+    elements
+}
+
+#[get("/archive/")]
+async fn archive(state: web::Data<State>) -> impl Responder {
+    handle_archive_page(&state, state.posts(), "Archive", "All", 1, "archive", &[])
+}
+
+#[get("/archive/page/{page_num}/")]
+async fn archive_page(
+    state: web::Data<State>,
+    web::Path(page_num): web::Path<usize>,
+) -> impl Responder {
+    handle_archive_page(
+        &state,
+        state.posts(),
+        "Archive",
+        "All",
+        page_num,
+        "archive",
+        &[],
+    )
+}
+
+#[get(r#"/archive/{year:\d{4}}/"#)]
+async fn archive_year(
+    state: web::Data<State>,
+    web::Path(year): web::Path<i32>,
+) -> impl Responder {
+    handle_archive_date_page(&state, year, None, 1)
+}
+
+#[get(r#"/archive/{year:\d{4}}/page/{page_num}/"#)]
+async fn archive_year_page(
     state: web::Data<State>,
-    web::Path((year, month, day, name)): web::Path<(u16, u8, u8, String)>,
+    web::Path((year, page_num)): web::Path<(i32, usize)>,
 ) -> impl Responder {
-    let post = state.instance.load_post(year, month, day, &name, false);
-    if post.is_err() {
+    handle_archive_date_page(&state, year, None, page_num)
+}
+
+#[get(r#"/archive/{year:\d{4}}/{month:\d{2}}/"#)]
+async fn archive_month(
+    state: web::Data<State>,
+    web::Path((year, month)): web::Path<(i32, u32)>,
+) -> impl Responder {
+    handle_archive_date_page(&state, year, Some(month), 1)
+}
+
+#[get(r#"/archive/{year:\d{4}}/{month:\d{2}}/page/{page_num}/"#)]
+async fn archive_month_page(
+    state: web::Data<State>,
+    web::Path((year, month, page_num)): web::Path<(i32, u32, usize)>,
+) -> impl Responder {
+    handle_archive_date_page(&state, year, Some(month), page_num)
+}
+
+/// Render a page of posts created in a year, or in a month of the year.
+fn handle_archive_date_page(
+    state: &web::Data<State>,
+    year: i32,
+    month: Option<u32>,
+    page_num: usize,
+) -> HttpResponse {
+    let posts = filter_posts_by_date(state.posts(), year, month);
+    if posts.is_empty() {
         return HttpResponse::NotFound().finish();
     }
-    let post = post.unwrap();
-    let mut context = new_context(&state);
-    context.insert("entry", &post);
-    HttpResponse::Ok().body(state.templates.render("post.html", &context).unwrap())
+    let elements = archive_date_elements(year, month);
+    let elements: Vec<&str> = elements.iter().map(|e| e.as_str()).collect();
+    let (archive_type, route) = match month {
+        Some(_) => ("Month", "archive_month"),
+        None => ("Year", "archive_year"),
+    };
+    handle_archive_page(
+        state,
+        posts,
+        archive_type,
+        &elements.join("-"),
+        page_num,
+        route,
+        &elements,
+    )
 }
 
-#[get("/archive/")]
-async fn archive(state: web::Data<State>, req: HttpRequest) -> impl Responder {
-    let posts = load_posts(&state.instance, &req, true);
-    let mut context = new_context(&state);
-    context.insert("entries", &posts);
-    context.insert("archive", &hashmap! {"type" => "Archive", "name" => "All"});
-    HttpResponse::Ok().body(state.templates.render("archive.html", &context).unwrap())
+/// Whether the theme lists terms of taxonomies, which is optional.
+pub(crate) fn has_taxonomy_list(instance: &Instance) -> bool {
+    instance
+        .template_folder
+        .join("taxonomy_list.html")
+        .is_file()
+}
+
+/// A term of a taxonomy, given to `taxonomy_list.html` in `terms`.
+#[derive(Debug, Serialize)]
+struct TermItem {
+    name: String,
+    slug: String,
+    count: usize,
+    url: String,
 }
 
-#[get("/category/{name}/")]
-async fn category(
+/// Render all terms of a taxonomy, with post counts.
+async fn taxonomy_list(
     state: web::Data<State>,
-    req: HttpRequest,
-    web::Path(name): web::Path<String>,
+    taxonomy: web::Data<Taxonomy>,
 ) -> impl Responder {
-    let posts = load_posts(&state.instance, &req, true);
+    if !has_taxonomy_list(&state.instance) {
+        return HttpResponse::NotFound().finish();
+    }
+    let terms: Vec<TermItem> = state.term_data().counts[&taxonomy.name]
+        .iter()
+        .map(|(slug, term)| TermItem {
+            name: term.name.clone(),
+            url: url_for(taxonomy.term_route(), &[slug]).unwrap(),
+            slug: slug.clone(),
+            count: term.count,
+        })
+        .collect();
+
     let mut context = new_context(&state);
+    context.insert("terms", &terms);
     context.insert(
-        "entries",
-        &posts
-            .iter()
-            .filter(|p| {
-                p.meta["categories"]
-                    .as_vec()
-                    .unwrap()
-                    .contains(&Yaml::String(name.clone()))
-            })
-            .collect::<Vec<&Entry>>(),
+        "taxonomy",
+        &hashmap! {"type" => taxonomy.title(), "name" => taxonomy.name.clone()},
     );
-    context.insert("archive", &hashmap! {"type" => "Category", "name" => &name});
-    HttpResponse::Ok().body(state.templates.render("archive.html", &context).unwrap())
+    state.render("taxonomy_list.html", &context)
 }
 
-#[get("/tag/{name}/")]
-async fn tag(
+/// Render posts of a term of a taxonomy.
+async fn term(
     state: web::Data<State>,
-    req: HttpRequest,
+    taxonomy: web::Data<Taxonomy>,
     web::Path(name): web::Path<String>,
 ) -> impl Responder {
-    let posts = load_posts(&state.instance, &req, true);
-    let mut context = new_context(&state);
+    handle_term_page(&state, &taxonomy, &name, 1)
+}
+
+async fn term_page(
+    state: web::Data<State>,
+    taxonomy: web::Data<Taxonomy>,
+    web::Path((name, page_num)): web::Path<(String, usize)>,
+) -> impl Responder {
+    handle_term_page(&state, &taxonomy, &name, page_num)
+}
+
+fn handle_term_page(
+    state: &web::Data<State>,
+    taxonomy: &Taxonomy,
+    name: &str,
+    page_num: usize,
+) -> HttpResponse {
+    let route = taxonomy.term_route();
+    let slug = taxonomy.slug(name);
+    if slug != name {
+        let url = if page_num > 1 {
+            url_for(&format!("{}_page", route), &[slug, page_num.to_string()])
+        } else {
+            url_for(route, &[slug])
+        };
+        return redirect_to(&url.unwrap());
+    }
+    let term_data = state.term_data();
+    let counts = &term_data.counts[&taxonomy.name];
+    let term = match counts.get(name) {
+        Some(term) => term,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    let posts = taxonomy.filter_posts(state.posts(), name);
+    let context = archive_context(
+        state,
+        posts,
+        &taxonomy.title(),
+        &term.name,
+        page_num,
+        route,
+        &[name],
+    );
+    let mut context = match context {
+        Some(context) => context,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    // the term among its ancestors and children, of which flat taxonomies have none
+    let term_url = |slug: &str| url_for(route, &[slug]).unwrap();
+    let children = find_term(&term_data.trees[&taxonomy.name], name)
+        .map(|node| node.children.as_slice())
+        .unwrap_or_default();
     context.insert(
-        "entries",
-        &posts
+        "breadcrumbs",
+        &term_breadcrumbs(taxonomy, name, counts, term_url),
+    );
+    context.insert("children", children);
+    state.render("archive.html", &context)
+}
+
+/// Respond with the Atom feed of posts of a term of a taxonomy.
+async fn term_atom(
+    state: web::Data<State>,
+    taxonomy: web::Data<Taxonomy>,
+    web::Path(name): web::Path<String>,
+) -> impl Responder {
+    if !taxonomy.feed {
+        return HttpResponse::NotFound().finish();
+    }
+    let route = taxonomy.term_route();
+    let slug = taxonomy.slug(&name);
+    if slug != name {
+        return redirect_to(&url_for(&format!("{}_atom", route), &[slug]).unwrap());
+    }
+    let term_name = match state.term_data().counts[&taxonomy.name].get(&slug) {
+        Some(term) => term.name.clone(),
+        None => return HttpResponse::NotFound().finish(),
+    };
+    handle_feed(
+        &state,
+        FeedFormat::Atom,
+        format!(
+            "{} - {}: {}",
+            state.instance.site.title,
+            taxonomy.title(),
+            term_name
+        ),
+        url_for(&format!("{}_atom", route), &[&slug]).unwrap(),
+        url_for(route, &[&slug]).unwrap(),
+        taxonomy.filter_posts(state.posts(), &slug),
+    )
+}
+
+/// Redirect permanently to the canonical URL of a resource,
+/// e.g. from a term name to its slug.
+fn redirect_to(url: &str) -> HttpResponse {
+    HttpResponse::MovedPermanently()
+        .header("Location", url)
+        .finish()
+}
+
+/// Respond with a feed of the latest posts in the given format.
+fn handle_feed(
+    state: &web::Data<State>,
+    format: FeedFormat,
+    title: String,
+    feed_url: String,
+    home_url: String,
+    posts: Vec<Arc<Entry>>,
+) -> HttpResponse {
+    if !state.instance.feed.formats.contains(&format) {
+        return HttpResponse::NotFound().finish();
+    }
+    let posts: Vec<Entry> = {
+        let content_index = state.index.read().unwrap();
+        posts
             .iter()
-            .filter(|p| {
-                p.meta["tags"]
-                    .as_vec()
-                    .unwrap()
-                    .contains(&Yaml::String(name.clone()))
-            })
-            .collect::<Vec<&Entry>>(),
+            .take(state.instance.feed.entries)
+            .map(|p| content_index.with_content(p))
+            .collect()
+    };
+    let feed = Feed {
+        title,
+        feed_url,
+        home_url,
+        entries: &posts,
+    };
+    let body = match format {
+        FeedFormat::Atom => feed.to_atom(&state.instance),
+        FeedFormat::Rss => feed.to_rss(&state.instance),
+        FeedFormat::Json => feed.to_json(&state.instance),
+    };
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .body(body)
+}
+
+#[get("/atom.xml")]
+async fn atom(state: web::Data<State>) -> impl Responder {
+    handle_feed(
+        &state,
+        FeedFormat::Atom,
+        state.instance.site.title.clone(),
+        url_for("atom", NO_ELEMENTS).unwrap(),
+        url_for("index", NO_ELEMENTS).unwrap(),
+        state.posts(),
+    )
+}
+
+#[get("/rss.xml")]
+async fn rss(state: web::Data<State>) -> impl Responder {
+    handle_feed(
+        &state,
+        FeedFormat::Rss,
+        state.instance.site.title.clone(),
+        url_for("rss", NO_ELEMENTS).unwrap(),
+        url_for("index", NO_ELEMENTS).unwrap(),
+        state.posts(),
+    )
+}
+
+#[get("/feed.json")]
+async fn json_feed(state: web::Data<State>) -> impl Responder {
+    handle_feed(
+        &state,
+        FeedFormat::Json,
+        state.instance.site.title.clone(),
+        url_for("json_feed", NO_ELEMENTS).unwrap(),
+        url_for("index", NO_ELEMENTS).unwrap(),
+        state.posts(),
+    )
+}
+
+#[get("/sitemap.xml")]
+async fn sitemap_xml(state: web::Data<State>) -> impl Responder {
+    let instance = &state.instance;
+    let posts = state.posts();
+    let latest = posts.iter().filter_map(|p| p.updated.or(p.created)).max();
+    let mut urls = vec![SitemapUrl {
+        loc: url_for("index", NO_ELEMENTS).unwrap(),
+        lastmod: latest,
+    }];
+    for page_num in 2..=instance.config.index_page_count(posts.len()) {
+        urls.push(SitemapUrl {
+            loc: url_for("index_page", &[page_num.to_string()]).unwrap(),
+            lastmod: None,
+        });
+    }
+    urls.extend(posts.iter().filter_map(|p| SitemapUrl::from_entry(p)));
+    urls.extend(
+        state
+            .index
+            .read()
+            .unwrap()
+            .pages()
+            .values()
+            .filter_map(|p| SitemapUrl::from_entry(p)),
+    );
+    urls.push(SitemapUrl {
+        loc: url_for("archive", NO_ELEMENTS).unwrap(),
+        lastmod: latest,
+    });
+    for page_num in 2..=instance.config.archive_page_count(posts.len()) {
+        urls.push(SitemapUrl {
+            loc: url_for("archive_page", &[page_num.to_string()]).unwrap(),
+            lastmod: None,
+        });
+    }
+    for ((year, month), post_count) in count_archive_periods(&posts) {
+        let route = if month.is_some() {
+            "archive_month"
+        } else {
+            "archive_year"
+        };
+        let elements = archive_date_elements(year, month);
+        urls.push(SitemapUrl {
+            loc: url_for(route, &elements).unwrap(),
+            lastmod: filter_posts_by_date(posts.clone(), year, month)
+                .iter()
+                .filter_map(|p| p.updated.or(p.created))
+                .max(),
+        });
+        for page_num in 2..=instance.config.archive_page_count(post_count) {
+            let mut elements = elements.clone();
+            elements.push(page_num.to_string());
+            urls.push(SitemapUrl {
+                loc: url_for(&format!("{}_page", route), &elements).unwrap(),
+                lastmod: None,
+            });
+        }
+    }
+    for taxonomy in &instance.taxonomies {
+        if has_taxonomy_list(instance) {
+            urls.push(SitemapUrl {
+                loc: url_for(&taxonomy.list_route(), NO_ELEMENTS).unwrap(),
+                lastmod: latest,
+            });
+        }
+        let route = taxonomy.term_route();
+        for slug in taxonomy.count_terms(&posts).keys() {
+            let term_posts = taxonomy.filter_posts(posts.clone(), slug);
+            let lastmod = term_posts
+                .iter()
+                .filter_map(|p| p.updated.or(p.created))
+                .max();
+            urls.push(SitemapUrl {
+                loc: url_for(route, &[slug]).unwrap(),
+                lastmod,
+            });
+            for page_num in 2..=instance.config.archive_page_count(term_posts.len()) {
+                urls.push(SitemapUrl {
+                    loc: url_for(
+                        &format!("{}_page", route),
+                        &[slug.clone(), page_num.to_string()],
+                    )
+                    .unwrap(),
+                    lastmod: None,
+                });
+            }
+        }
+    }
+    HttpResponse::Ok()
+        .content_type("application/xml; charset=utf-8")
+        .body(sitemap(instance, &urls))
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    page: Option<usize>,
+}
+
+#[get("/search/", name = "search")]
+async fn search_page(
+    state: web::Data<State>,
+    req: HttpRequest,
+    web::Query(query): web::Query<SearchQuery>,
+) -> impl Responder {
+    let results_per_page = state.instance.config.posts_per_index_page as usize;
+    let page_num = query.page.unwrap_or(1);
+    if page_num < 1 {
+        return HttpResponse::NotFound().finish();
+    }
+    let (result_count, results) = state.search_index().search(
+        &query.q,
+        (page_num - 1) * results_per_page,
+        results_per_page,
     );
-    context.insert("archive", &hashmap! {"type" => "Tag", "name" => &name});
-    HttpResponse::Ok().body(state.templates.render("archive.html", &context).unwrap())
+    let page_count = (result_count + results_per_page - 1) / results_per_page;
+    if page_num > page_count.max(1) {
+        return HttpResponse::NotFound().finish();
+    }
+    let pager = Pager::new(page_num, page_count, |page_num| {
+        let mut url = format!(
+            "{}?q={}",
+            req.url_for_static("search").unwrap().path(),
+            utf8_percent_encode(&query.q, NON_ALPHANUMERIC)
+        );
+        if page_num > 1 {
+            url.push_str(&format!("&page={}", page_num));
+        }
+        url
+    });
+
+    let mut context = new_context(&state);
+    context.insert("query", &query.q);
+    context.insert("results", &results);
+    context.insert("result_count", &result_count);
+    context.insert("pager", &pager);
+    state.render("search.html", &context)
+}
+
+#[get("/search-index.json")]
+async fn search_index_json(state: web::Data<State>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .body(serde_json::to_string(&state.search_documents()).unwrap())
+}
+
+#[get("/robots.txt")]
+async fn robots_txt(state: web::Data<State>, req: HttpRequest) -> impl Responder {
+    // a custom robots.txt in the raw folder takes precedence
+    if let Ok(file) = actix_files::NamedFile::open(state.instance.raw_folder.join("robots.txt")) {
+        return file.into_response(&req).unwrap();
+    }
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(robots(
+            &state.instance,
+            &url_for("sitemap_xml", NO_ELEMENTS).unwrap(),
+        ))
+}
+
+#[get("/__pressure/live-reload")]
+async fn live_reload_events(state: web::Data<State>) -> impl Responder {
+    match &state.live_reload {
+        Some(live_reload) => HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .header("Cache-Control", "no-cache")
+            .streaming(live_reload.subscribe()),
+        None => HttpResponse::NotFound().finish(),
+    }
 }
 
 #[get("/static/{filename:.*}", name = "static")]
@@ -192,16 +818,26 @@ async fn theme_static(
     )?)
 }
 
+/// Render the post or page at the URL, which follows permalink templates or front matter,
+/// or respond with a raw file.
 #[get("/{rel_url:.*}")]
 async fn page(
     state: web::Data<State>,
     req: HttpRequest,
     web::Path(rel_url): web::Path<PathBuf>,
 ) -> impl Responder {
-    if let Ok(page) = state.instance.load_page(&rel_url) {
+    let entry = {
+        let content_index = state.index.read().unwrap();
+        content_index.entry(&percent_decode_str(req.path()).decode_utf8_lossy())
+    };
+    if let Some(entry) = entry {
+        let template_name = match entry.etype {
+            EntryType::Post => "post.html",
+            _ => "page.html",
+        };
         let mut context = new_context(&state);
-        context.insert("entry", &page);
-        HttpResponse::Ok().body(state.templates.render("page.html", &context).unwrap())
+        context.insert("entry", &entry);
+        state.render(template_name, &context)
     } else {
         let filepath = state.instance.raw_folder.join(&rel_url);
         if !filepath.starts_with(&state.instance.raw_folder) {
@@ -219,7 +855,42 @@ thread_local! {
     static ROUTES_KEY: OnceCell<ResourceMap> = OnceCell::new();
 }
 
-fn tera_url_for(args: &HashMap<String, tera::Value>) -> Result<tera::Value, tera::Error> {
+/// Remember the resource map of the app, so that URLs can be generated
+/// outside of handlers (e.g. in templates).
+fn remember_routes(req: &ServiceRequest) {
+    ROUTES_KEY.with(|routes| {
+        routes.get_or_init(|| req.resource_map().clone());
+    });
+}
+
+/// Elements for resources without dynamic segments.
+pub(crate) const NO_ELEMENTS: [&str; 0] = [];
+
+/// Generate the URL path of a named resource.
+/// Must be called on a thread that has handled at least one request.
+pub(crate) fn url_for<U, I>(name: &str, elements: U) -> PressResult<String>
+where
+    U: IntoIterator<Item = I>,
+    I: AsRef<str>,
+{
+    ROUTES_KEY.with(|routes| {
+        let routes = routes.get().ok_or(PressError::new(
+            "`url_for` should only be called in request context",
+        ))?;
+        let fake_req = TestRequest::default().to_http_request();
+        let url = routes
+            .url_for(&fake_req, name, elements)
+            .or(Err(PressError::new("resource not found")))?;
+        Ok(url.path().to_string())
+    })
+}
+
+/// `url_for` in templates, where terms of taxonomies given to their routes are turned into
+/// slugs, e.g. `url_for(name="tag", elements=["C++"])` -> `/tag/c-plus-plus/`.
+fn tera_url_for(
+    taxonomies: &[Taxonomy],
+    args: &HashMap<String, tera::Value>,
+) -> Result<tera::Value, tera::Error> {
     let name = args["name"]
         .as_str()
         .ok_or(tera::Error::msg("`name` should be a string"))?;
@@ -232,68 +903,421 @@ fn tera_url_for(args: &HashMap<String, tera::Value>) -> Result<tera::Value, tera
         .iter();
     let mut elements = vec![];
     for elem in elements_iter {
-        elements.push(elem.as_str().ok_or(tera::Error::msg(
-            "`elements` array should contain only strings",
-        ))?);
+        elements.push(
+            elem.as_str()
+                .ok_or(tera::Error::msg(
+                    "`elements` array should contain only strings",
+                ))?
+                .to_string(),
+        );
     }
-    ROUTES_KEY.with(|routes| {
-        let routes = routes.get().ok_or(tera::Error::msg(
-            "`url_for` should only be called in request context",
-        ))?;
-        let fake_req = TestRequest::default().to_http_request();
-        let url = routes
-            .url_for(&fake_req, name, elements)
-            .or(Err(tera::Error::msg("resource not found")))?;
-        Ok(tera::Value::String(url.path().to_string())) // TODO: prepend url root
-    })
+    let taxonomy = taxonomies.iter().find(|t| {
+        let route = t.term_route();
+        name == route || name == format!("{}_page", route) || name == format!("{}_atom", route)
+    });
+    if let (Some(taxonomy), Some(term)) = (taxonomy, elements.first_mut()) {
+        *term = taxonomy.slug(term);
+    }
+    url_for(name, elements)
+        .map(tera::Value::String) // TODO: prepend url root
+        .map_err(|err| tera::Error::msg(err.to_string()))
 }
 
 struct State {
     instance: Instance,
-    templates: Tera,
+    templates: Arc<RwLock<Tera>>,
+    index: SharedIndex,
+    /// Browser tabs to reload, only in development mode.
+    live_reload: Option<Arc<LiveReload>>,
+    /// Search index built on demand.
+    search_index: Derived<SearchIndex>,
+    /// Terms of taxonomies, counted on demand.
+    term_data: Derived<TermData>,
+}
+
+/// Data derived from published posts on demand, along with the state of the content index it
+/// was derived from, so that it's only derived again once posts are changed or published.
+struct Derived<T>(Mutex<Option<(PublishedKey, Arc<T>)>>);
+
+impl<T> Derived<T> {
+    fn new() -> Derived<T> {
+        Derived(Mutex::new(None))
+    }
+
+    /// Get the data derived from the given state of the content index, or derive it again.
+    fn get<F: FnOnce() -> T>(&self, key: PublishedKey, derive: F) -> Arc<T> {
+        let mut cached = self.0.lock().unwrap();
+        if let Some((cached_key, data)) = &*cached {
+            if *cached_key == key {
+                return data.clone();
+            }
+        }
+        let data = Arc::new(derive());
+        *cached = Some((key, data.clone()));
+        data
+    }
+}
+
+/// Terms of all taxonomies among published posts, so that rendering doesn't go over all posts.
+struct TermData {
+    /// Post counts of terms keyed by slugs, by names of taxonomies.
+    counts: HashMap<String, BTreeMap<String, TermCount>>,
+    /// Trees of terms by names of taxonomies, where terms of flat taxonomies are all roots.
+    trees: HashMap<String, Vec<TermNode>>,
+    /// Post counts keyed by names of terms, by names of taxonomies, given to templates as
+    /// `taxonomies`.
+    totals: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+impl TermData {
+    fn new(taxonomies: &[Taxonomy], posts: &[Arc<Entry>]) -> TermData {
+        let mut data = TermData {
+            counts: HashMap::new(),
+            trees: HashMap::new(),
+            totals: BTreeMap::new(),
+        };
+        for taxonomy in taxonomies {
+            let counts = taxonomy.count_terms(posts);
+            let tree = term_tree(&counts, |slug| {
+                url_for(taxonomy.term_route(), &[slug]).unwrap()
+            });
+            let totals = counts
+                .values()
+                .map(|term| (term.name.clone(), term.count))
+                .collect();
+            data.counts.insert(taxonomy.name.clone(), counts);
+            data.trees.insert(taxonomy.name.clone(), tree);
+            data.totals.insert(taxonomy.name.clone(), totals);
+        }
+        data
+    }
+}
+
+impl State {
+    fn new(instance: &Instance, live_reload: Option<Arc<LiveReload>>) -> PressResult<State> {
+        Ok(State {
+            instance: instance.clone(),
+            templates: Arc::new(RwLock::new(load_templates(instance)?)),
+            index: Arc::new(RwLock::new(ContentIndex::new(instance)?)),
+            live_reload,
+            search_index: Derived::new(),
+            term_data: Derived::new(),
+        })
+    }
+
+    /// Get all published posts without content from the index, shared rather than cloned.
+    fn posts(&self) -> Vec<Arc<Entry>> {
+        self.index.read().unwrap().posts()
+    }
+
+    /// Get search documents of all posts and pages.
+    fn search_documents(&self) -> Vec<SearchDocument> {
+        let content_index = self.index.read().unwrap();
+        let posts = content_index
+            .posts()
+            .into_iter()
+            .filter_map(|p| SearchDocument::from_entry(&content_index.with_content(&p)));
+        let pages = content_index
+            .pages()
+            .values()
+            .filter_map(|p| SearchDocument::from_entry(p));
+        posts.chain(pages).collect()
+    }
+
+    /// Get the search index, which is rebuilt if posts or pages have changed since last time.
+    fn search_index(&self) -> Arc<SearchIndex> {
+        // scheduled posts may be published without changing the index
+        let key = self.index.read().unwrap().published_key();
+        self.search_index
+            .get(key, || SearchIndex::new(self.search_documents()))
+    }
+
+    /// Get terms of taxonomies, which are counted again if posts have changed since last time.
+    fn term_data(&self) -> Arc<TermData> {
+        let key = self.index.read().unwrap().published_key();
+        self.term_data
+            .get(key, || TermData::new(&self.instance.taxonomies, &self.posts()))
+    }
+
+    /// Render a template into an HTML response.
+    fn render(&self, template_name: &str, context: &Context) -> HttpResponse {
+        let html = match self.templates.read().unwrap().render(template_name, context) {
+            Ok(html) => html,
+            Err(err) => {
+                log::error!("Failed to render {}: {:?}", template_name, err);
+                return HttpResponse::InternalServerError().finish();
+            }
+        };
+        let html = match self.live_reload {
+            Some(_) => inject_script(
+                html,
+                &url_for("live_reload_events", NO_ELEMENTS).unwrap(),
+            ),
+            None => html,
+        };
+        HttpResponse::Ok().body(html)
+    }
+}
+
+fn load_templates(instance: &Instance) -> PressResult<Tera> {
+    let mut tera = Tera::new(
+        instance
+            .template_folder
+            .join("**")
+            .join("*.html")
+            .to_str()
+            .unwrap(),
+    )?;
+    let taxonomies = instance.taxonomies.clone();
+    tera.register_function("url_for", move |args: &HashMap<String, tera::Value>| {
+        tera_url_for(&taxonomies, args)
+    });
+    Ok(tera)
+}
+
+/// Check if templates of the theme can be parsed.
+pub fn check_templates(instance: &Instance) -> PressResult<()> {
+    load_templates(instance).map(|_| ())
+}
+
+/// Register app data and all routes of a Pressure instance.
+fn configure_app(cfg: &mut web::ServiceConfig, state: &web::Data<State>) {
+    cfg.app_data(state.clone())
+        .service(index)
+        .service(index_page)
+        .service(archive)
+        .service(archive_page)
+        .service(archive_year)
+        .service(archive_year_page)
+        .service(archive_month)
+        .service(archive_month_page);
+    for taxonomy in &state.instance.taxonomies {
+        configure_taxonomy(cfg, taxonomy);
+    }
+    cfg.service(atom)
+        .service(rss)
+        .service(json_feed)
+        .service(sitemap_xml)
+        .service(search_page)
+        .service(search_index_json)
+        .service(robots_txt)
+        .service(live_reload_events)
+        .service(root_static)
+        .service(theme_static)
+        .service(page);
+    // posts are served by `page`, since entries may have their own permalinks,
+    // and the route is only for generating URLs
+    cfg.external_resource(
+        "post",
+        state
+            .instance
+            .permalinks
+            .post_route(&state.instance.taxonomies),
+    );
+}
+
+/// Register routes of a taxonomy, whose handlers get the taxonomy as resource data.
+fn configure_taxonomy(cfg: &mut web::ServiceConfig, taxonomy: &Taxonomy) {
+    let data = web::Data::new(taxonomy.clone());
+    let route = taxonomy.term_route();
+    // terms of hierarchical taxonomies span multiple segments, e.g. /category/Dev/Rust/
+    let name = if taxonomy.hierarchical {
+        "{name:.+}"
+    } else {
+        "{name}"
+    };
+    cfg.service(
+        web::resource(format!("/{}/", taxonomy.name))
+            .name(&taxonomy.list_route())
+            .app_data(data.clone())
+            .route(web::get().to(taxonomy_list)),
+    )
+    // pages go first, so that they are not taken as descendants
+    .service(
+        web::resource(format!("/{}/{}/page/{{page_num:\\d+}}/", route, name))
+            .name(&format!("{}_page", route))
+            .app_data(data.clone())
+            .route(web::get().to(term_page)),
+    )
+    .service(
+        web::resource(format!("/{}/{}/", route, name))
+            .name(route)
+            .app_data(data.clone())
+            .route(web::get().to(term)),
+    )
+    .service(
+        web::resource(format!("/{}/{}/atom.xml", route, name))
+            .name(&format!("{}_atom", route))
+            .app_data(data)
+            .route(web::get().to(term_atom)),
+    );
+}
+
+/// Create a Pressure app, which can be used both by the web server and the static builder.
+macro_rules! pressure_app {
+    ($state:expr) => {
+        App::new()
+            .wrap_fn(|req, srv| {
+                remember_routes(&req);
+                srv.call(req)
+            })
+            .configure(|cfg| configure_app(cfg, $state))
+    };
+}
+
+/// Create an in-process Pressure app service, which handles requests without binding any port.
+pub(crate) async fn init_app_service(
+    instance: &Instance,
+) -> PressResult<
+    impl Service<
+        Request = actix_http::Request,
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+    >,
+> {
+    let state = web::Data::new(State::new(instance, None)?);
+    Ok(actix_web::test::init_service(pressure_app!(&state)).await)
 }
 
 /// Serve Pressure instance as a web app.
-pub fn serve(instance: Instance, host: &str, port: u16) -> PressResult<()> {
+/// In development mode, templates are reloaded when changed, browser tabs are reloaded
+/// when anything changes, and the server restarts when `pressure.toml` changes.
+pub fn serve(instance: Instance, host: &str, port: u16, dev: bool) -> PressResult<()> {
     let addr = format!("{}:{}", host, port);
-    Ok(actix_web::rt::System::new("main").block_on(async move {
-        HttpServer::new(move || {
-            let mut tera = Tera::new(
-                instance
-                    .template_folder
-                    .join("**")
-                    .join("*.html")
-                    .to_str()
-                    .unwrap(),
-            )
-            .expect("Failed to parse templates.");
-
-            tera.register_function("url_for", tera_url_for);
-
-            App::new()
-                .app_data(web::Data::new(State {
-                    instance: instance.clone(),
-                    templates: tera,
-                }))
-                .wrap(Logger::default())
-                .wrap_fn(move |req, srv| {
-                    ROUTES_KEY.with(|routes| {
-                        routes.get_or_init(|| req.resource_map().clone());
-                    });
-                    srv.call(req)
-                })
-                .service(index)
-                .service(index_page)
-                .service(post)
-                .service(archive)
-                .service(category)
-                .service(tag)
-                .service(root_static)
-                .service(theme_static)
-                .service(page)
-        })
-        .bind(addr)?
-        .run()
-        .await
-    })?)
+    actix_web::rt::System::new("main").block_on(async move {
+        let mut instance = instance;
+        loop {
+            let live_reload = if dev {
+                Some(Arc::new(LiveReload::default()))
+            } else {
+                None
+            };
+            // the state is shared by all workers, and kept up to date by file watchers
+            let state = web::Data::new(State::new(&instance, live_reload.clone())?);
+            let server = {
+                let state = state.clone();
+                HttpServer::new(move || pressure_app!(&state).wrap(Logger::default()))
+                    .bind(&addr)?
+                    .run()
+            };
+
+            let _content_watcher = watch_content(&instance, state.index.clone(), {
+                let live_reload = live_reload.clone();
+                move || live_reload.iter().for_each(|lr| lr.reload())
+            })?;
+            let restart = Arc::new(AtomicBool::new(false));
+            let _site_watcher = match &live_reload {
+                Some(live_reload) => Some(watch_site(
+                    &instance,
+                    state.templates.clone(),
+                    live_reload.clone(),
+                    {
+                        let (server, restart, live_reload) =
+                            (server.clone(), restart.clone(), live_reload.clone());
+                        move || {
+                            restart.store(true, Ordering::SeqCst);
+                            live_reload.disconnect();
+                            futures::executor::block_on(server.stop(false));
+                        }
+                    },
+                )?),
+                None => None,
+            };
+
+            server.await?;
+            if !restart.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            match Instance::new(&instance.root_folder) {
+                Ok(new_instance) => {
+                    instance = Instance {
+                        include_drafts: instance.include_drafts,
+                        ..new_instance
+                    }
+                }
+                Err(err) => log::warn!("Failed to reload pressure.toml: {}", err),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::call_service;
+    use chrono::{FixedOffset, TimeZone};
+
+    use super::*;
+
+    #[test]
+    fn test_pager() {
+        let page_url = |num: usize| format!("/page/{}/", num);
+        let pager = Pager::new(1, 3, page_url);
+        assert_eq!(pager.prev_url, "");
+        assert_eq!(pager.next_url, "/page/2/");
+        assert_eq!(pager.pages.len(), 3);
+        assert!(pager.pages[0].current && !pager.pages[1].current);
+
+        let pager = Pager::new(3, 3, page_url);
+        assert_eq!(pager.prev_url, "/page/2/");
+        assert_eq!(pager.next_url, "");
+        assert!(pager.pages[2].current);
+
+        let pager = Pager::new(1, 1, page_url);
+        assert_eq!((pager.prev_url.as_str(), pager.next_url.as_str()), ("", ""));
+    }
+
+    #[test]
+    fn test_paginate() {
+        let mut items: Vec<usize> = (0..7).collect();
+        assert_eq!(paginate(&mut items, 1, 3, 3).unwrap(), &[0, 1, 2]);
+        // the last page is partial
+        assert_eq!(paginate(&mut items, 3, 3, 3).unwrap(), &[6]);
+        assert!(paginate(&mut items, 0, 3, 3).is_none());
+        assert!(paginate(&mut items, 4, 3, 3).is_none());
+
+        // an empty listing still has its first page
+        let inst = Instance::new("tests/test_inst").unwrap();
+        let page_count = inst.config.archive_page_count(0);
+        assert_eq!(page_count, 1);
+        let mut items: Vec<usize> = vec![];
+        assert!(paginate(&mut items, 1, page_count, 3).unwrap().is_empty());
+        assert!(paginate(&mut items, 2, page_count, 3).is_none());
+    }
+
+    #[test]
+    fn test_group_by_month() {
+        // `url_for` works once the app has handled a request
+        let inst = Instance::new("tests/test_inst").unwrap();
+        actix_web::rt::System::new("test").block_on(async move {
+            let mut app = init_app_service(&inst).await.unwrap();
+            call_service(&mut app, TestRequest::get().uri("/").to_request()).await;
+        });
+
+        let tz = FixedOffset::east(8 * 3600);
+        let posts: Vec<Entry> = vec![(2021, 3, 2), (2021, 3, 1), (2021, 1, 5), (2020, 12, 31)]
+            .into_iter()
+            .map(|(y, m, d)| Entry {
+                created: Some(tz.ymd(y, m, d).and_hms(0, 0, 0)),
+                ..Entry::default()
+            })
+            .chain(std::iter::once(Entry::default()))
+            .collect();
+        let counts = count_archive_periods(&posts);
+        assert_eq!(counts[&(2021, None)], 3);
+        assert_eq!(counts[&(2021, Some(3))], 2);
+
+        let years = group_by_month(&posts, &counts);
+        assert_eq!(years.len(), 2);
+        assert_eq!(years[0].url, "/archive/2021/");
+        assert_eq!(years[0].count, 3);
+        let months: Vec<(u32, usize)> = years[0]
+            .months
+            .iter()
+            .map(|m| (m.month, m.entries.len()))
+            .collect();
+        assert_eq!(months, vec![(3, 2), (1, 1)]);
+        assert_eq!(years[0].months[1].url, "/archive/2021/01/");
+        // posts without creation time are left out
+        assert_eq!(years[1].months[0].entries.len(), 1);
+    }
 }